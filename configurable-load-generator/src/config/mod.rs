@@ -27,6 +27,30 @@ impl Display for RampStrategy {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub(crate) enum TargetKind {
+    #[serde(alias = "HTTP")]
+    Http,
+    #[serde(alias = "WEBSOCKET")]
+    WebSocket,
+    #[serde(alias = "GRPC")]
+    Grpc,
+}
+
+impl Display for TargetKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Http => "HTTP",
+            Self::WebSocket => "WEBSOCKET",
+            Self::Grpc => "GRPC",
+        })
+    }
+}
+
+fn default_kind() -> TargetKind {
+    TargetKind::Http
+}
+
 fn default_method() -> Method {
     Method::Get
 }
@@ -70,10 +94,18 @@ fn default_client_wait_jitter() -> u64 {
 fn default_client_wait_decay_strategy() -> RampStrategy {
     RampStrategy::Step
 }
+fn default_instance_header() -> String {
+    "x-instance-id".to_owned()
+}
+fn default_message_size() -> usize {
+    32
+}
 
 #[derive(Debug, Clone, Deserialize)]
 pub(crate) struct TargetConfig {
     pub target: String,
+    #[serde(default = "default_kind")]
+    pub kind: TargetKind,
     #[serde(default = "default_method")]
     pub method: Method,
     #[serde(default = "default_client_timeout")]
@@ -100,14 +132,19 @@ pub(crate) struct TargetConfig {
     pub client_wait_jitter: u64,
     #[serde(default = "default_client_wait_decay_strategy")]
     pub client_wait_decay_strategy: RampStrategy,
+    #[serde(default = "default_instance_header")]
+    pub instance_header: String,
+    /// Size in bytes of the messages sent by WebSocket and gRPC clients.
+    #[serde(default = "default_message_size")]
+    pub message_size: usize,
 }
 
 impl Display for TargetConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(
             format!(
-                "{{target: \"{}\", method: \"{}\", client_count_start: \"{}\", client_count_ramp: \"{}\", client_count_ramp_interval: \"{}\", client_count_ramp_strategy: \"{}\", client_wait: \"{}\", client_wait_decay: \"{}\", client_wait_decay_interval: \"{}\", client_wait_decay_strategy: \"{}\"}}",
-                self.target, self.method,
+                "{{target: \"{}\", kind: \"{}\", method: \"{}\", client_count_start: \"{}\", client_count_ramp: \"{}\", client_count_ramp_interval: \"{}\", client_count_ramp_strategy: \"{}\", client_wait: \"{}\", client_wait_decay: \"{}\", client_wait_decay_interval: \"{}\", client_wait_decay_strategy: \"{}\", instance_header: \"{}\"}}",
+                self.target, self.kind, self.method,
                 self.client_count_start,
                 self.client_count_ramp,
                 self.client_count_ramp_interval,
@@ -116,6 +153,7 @@ impl Display for TargetConfig {
                 self.client_wait_decay,
                 self.client_wait_decay_interval,
                 self.client_wait_decay_strategy,
+                self.instance_header,
             )
             .as_str(),
         )
@@ -7,6 +7,7 @@ use std::{
     time::Duration,
 };
 
+use itertools::Itertools;
 use log::info;
 use tokio::time::{Instant, sleep};
 
@@ -67,6 +68,13 @@ impl StatisticsManager {
         let requests_per_second = requests_for_iteration as f64 / seconds_elapsed;
         let average_response_time = request_time_acc / requests_for_iteration;
 
+        let instances = std::mem::take(
+            &mut *stats
+                .instances
+                .write()
+                .expect("Failed to aquire write lock"),
+        );
+
         println!(
             "Stats for target: {}, clients: {}, requests/s: {}, avg response time: {}ms",
             name,
@@ -74,6 +82,23 @@ impl StatisticsManager {
             requests_per_second as usize,
             average_response_time,
         );
+
+        if !instances.is_empty() {
+            println!(
+                "Instance distribution for target: {}, {}",
+                name,
+                instances
+                    .iter()
+                    .sorted()
+                    .map(|(id, count)| format!(
+                        "{}: {} ({:.1}%)",
+                        id,
+                        count,
+                        *count as f64 * 100.0 / requests_for_iteration as f64
+                    ))
+                    .join(", "),
+            );
+        }
     }
 }
 
@@ -82,4 +107,6 @@ pub(crate) struct TargetStatistics {
     pub requests: Arc<AtomicUsize>,
     pub response_time_acc: Arc<AtomicUsize>,
     pub clients: Arc<AtomicUsize>,
+    /// Responses per instance id, as reported by the target's instance header.
+    pub instances: Arc<RwLock<HashMap<String, usize>>>,
 }
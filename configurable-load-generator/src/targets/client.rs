@@ -1,5 +1,6 @@
 use std::{
-    sync::{Arc, atomic::AtomicUsize},
+    collections::HashMap,
+    sync::{Arc, RwLock, atomic::AtomicUsize},
     time::Duration,
 };
 
@@ -12,7 +13,7 @@ use tokio::time::{Instant, sleep};
 use crate::{
     config::{RampStrategy, TargetConfig},
     stats::TargetStatistics,
-    targets::error::ClientTargetError,
+    targets::{ClientExit, error::ClientTargetError},
 };
 
 #[derive()]
@@ -27,6 +28,8 @@ pub(crate) struct ClientTarget {
     pub _wait_jitter: Duration,
     pub request_statistics: Arc<AtomicUsize>,
     pub response_time_acc: Arc<AtomicUsize>,
+    pub instance_header: String,
+    pub instance_statistics: Arc<RwLock<HashMap<String, usize>>>,
 }
 
 impl ClientTarget {
@@ -51,12 +54,14 @@ impl ClientTarget {
             _wait_jitter: Duration::from_millis(target_config.client_wait_jitter),
             request_statistics: statistics.requests.clone(),
             response_time_acc: statistics.response_time_acc.clone(),
+            instance_header: target_config.instance_header.clone(),
+            instance_statistics: statistics.instances.clone(),
         }
     }
 }
 
 impl ClientTarget {
-    pub(crate) async fn run_client(self) -> Result<(), ClientTargetError> {
+    pub(crate) async fn run_client(self) -> Result<ClientExit, ClientTargetError> {
         loop {
             let request_start_time = Instant::now();
 
@@ -84,6 +89,11 @@ impl ClientTarget {
         match self.client.get(self.target.as_str()).send().await {
             Ok(r) => {
                 let status = r.status();
+                let instance = r
+                    .headers()
+                    .get(self.instance_header.as_str())
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_owned);
                 let bytes = r
                     .bytes()
                     .map_err(|e| ClientTargetError::RequestFailure {
@@ -103,6 +113,10 @@ impl ClientTarget {
                     request_start_time.elapsed().as_millis() as usize,
                     std::sync::atomic::Ordering::Relaxed,
                 );
+
+                if let Some(instance) = instance {
+                    self.record_instance(instance);
+                }
             }
             Err(e) => {
                 eprintln!("{}", e);
@@ -122,6 +136,15 @@ impl ClientTarget {
         Ok(())
     }
 
+    fn record_instance(&self, instance: String) {
+        *self
+            .instance_statistics
+            .write()
+            .expect("Failed to aquire write lock")
+            .entry(instance)
+            .or_default() += 1;
+    }
+
     async fn handle_post(&self, _request_start_time: Instant) -> Result<(), ClientTargetError> {
         unimplemented!("handle_post");
     }
@@ -15,4 +15,8 @@ pub(crate) enum ClientTargetError {
         request: bool,
         connection: bool,
     },
+    #[error("WebSocket failure: {0}")]
+    WebSocketFailure(String),
+    #[error("gRPC failure: {0}")]
+    Grpc(String),
 }
@@ -0,0 +1,102 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock, atomic::AtomicUsize},
+    time::Duration,
+};
+
+use log::trace;
+use shared::grpc::{EchoRequest, EchoResponse, UNARY_PATH};
+use tokio::time::{Instant, sleep};
+use tonic::{
+    client::Grpc,
+    codegen::http::uri::PathAndQuery,
+    transport::{Channel, Endpoint},
+};
+use tonic_prost::ProstCodec;
+
+use crate::{
+    config::TargetConfig,
+    stats::TargetStatistics,
+    targets::{ClientExit, error::ClientTargetError},
+};
+
+/// Client calling the unary method of the test API `testapi.Echo` service over a single HTTP/2
+/// connection.
+pub(crate) struct GrpcClientTarget {
+    pub target: String,
+    pub timeout: Duration,
+    pub current_wait: Duration,
+    pub message: String,
+    pub request_statistics: Arc<AtomicUsize>,
+    pub response_time_acc: Arc<AtomicUsize>,
+    pub instance_statistics: Arc<RwLock<HashMap<String, usize>>>,
+}
+
+impl GrpcClientTarget {
+    pub(crate) fn new(target_config: &TargetConfig, statistics: &TargetStatistics) -> Self {
+        trace!("Creating gRPC client with target: {}", target_config.target);
+
+        Self {
+            target: target_config.target.clone(),
+            timeout: Duration::from_millis(target_config.client_timeout),
+            current_wait: Duration::from_millis(target_config.client_wait_start),
+            message: "x".repeat(target_config.message_size),
+            request_statistics: statistics.requests.clone(),
+            response_time_acc: statistics.response_time_acc.clone(),
+            instance_statistics: statistics.instances.clone(),
+        }
+    }
+
+    fn record_instance(&self, instance: String) {
+        *self
+            .instance_statistics
+            .write()
+            .expect("Failed to aquire write lock")
+            .entry(instance)
+            .or_default() += 1;
+    }
+
+    pub(crate) async fn run_client(self) -> Result<ClientExit, ClientTargetError> {
+        let channel: Channel = Endpoint::from_shared(self.target.clone())
+            .map_err(|e| ClientTargetError::Grpc(e.to_string()))?
+            .connect_timeout(self.timeout)
+            .timeout(self.timeout)
+            .connect()
+            .await
+            .map_err(|e| ClientTargetError::Grpc(e.to_string()))?;
+
+        let mut client = Grpc::new(channel);
+
+        loop {
+            let request_start_time = Instant::now();
+
+            client
+                .ready()
+                .await
+                .map_err(|e| ClientTargetError::Grpc(e.to_string()))?;
+
+            let response: tonic::Response<EchoResponse> = client
+                .unary(
+                    tonic::Request::new(EchoRequest {
+                        message: self.message.clone(),
+                        count: 0,
+                    }),
+                    PathAndQuery::from_static(UNARY_PATH),
+                    ProstCodec::default(),
+                )
+                .await
+                .map_err(|e| ClientTargetError::Grpc(e.to_string()))?;
+
+            self.request_statistics
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            self.response_time_acc.fetch_add(
+                request_start_time.elapsed().as_millis() as usize,
+                std::sync::atomic::Ordering::Relaxed,
+            );
+
+            self.record_instance(response.into_inner().instance);
+
+            sleep(self.current_wait).await
+        }
+    }
+}
@@ -5,20 +5,32 @@ use log::info;
 use tokio::{select, task::JoinHandle, time::sleep};
 
 use crate::{
-    config::{RampStrategy, TargetConfig},
+    config::{RampStrategy, TargetConfig, TargetKind},
     stats::{StatisticsManager, TargetStatistics},
     targets::{
         client::ClientTarget,
         error::{ClientTargetError, ClientTargetsError},
+        grpc::GrpcClientTarget,
+        websocket::WebSocketClientTarget,
         window::SlidingFailureWindow,
     },
 };
 
 mod client;
 mod error;
+mod grpc;
+mod websocket;
 mod window;
 
-type ClientThreadFutures = FuturesUnordered<Pin<Box<JoinHandle<Result<(), ClientTargetError>>>>>;
+type ClientThreadFutures =
+    FuturesUnordered<Pin<Box<JoinHandle<Result<ClientExit, ClientTargetError>>>>>;
+
+/// Reason a client finished without failing.
+#[derive(Debug)]
+pub(crate) enum ClientExit {
+    /// The target closed the connection, e.g. after a message limit.
+    Closed,
+}
 
 #[derive()]
 pub(crate) struct ClientTargets {
@@ -67,10 +79,19 @@ impl ClientTargets {
         println!("Creating {current_ramp} client targets");
 
         for _ in 0..current_ramp {
-            let client = ClientTarget::new(&self.target_config, &self.statistics);
-
-            self.client_target_threads
-                .push(Box::pin(tokio::spawn(client.run_client())));
+            let handle = match self.target_config.kind {
+                TargetKind::Http => tokio::spawn(
+                    ClientTarget::new(&self.target_config, &self.statistics).run_client(),
+                ),
+                TargetKind::WebSocket => tokio::spawn(
+                    WebSocketClientTarget::new(&self.target_config, &self.statistics).run_client(),
+                ),
+                TargetKind::Grpc => tokio::spawn(
+                    GrpcClientTarget::new(&self.target_config, &self.statistics).run_client(),
+                ),
+            };
+
+            self.client_target_threads.push(Box::pin(handle));
         }
 
         self.statistics
@@ -124,8 +145,8 @@ impl ClientTargets {
                           self.create_client_targets(1);
                         }
                       },
-                      Some(Ok(_)) => {
-                        eprintln!("Client thread exited for unknown reason");
+                      Some(Ok(Ok(ClientExit::Closed))) => {
+                        info!("Connection closed by target: {}, replacing client", self.name);
                         self.create_client_targets(1);
                       }
                       None => {
@@ -0,0 +1,83 @@
+use std::{
+    sync::{Arc, atomic::AtomicUsize},
+    time::Duration,
+};
+
+use futures::{SinkExt, StreamExt};
+use log::trace;
+use tokio::time::{Instant, sleep, timeout};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::{
+    config::TargetConfig,
+    stats::TargetStatistics,
+    targets::{ClientExit, error::ClientTargetError},
+};
+
+/// Client holding a single WebSocket connection open, measuring the round trip time of each
+/// message echoed back by the target.
+pub(crate) struct WebSocketClientTarget {
+    pub target: String,
+    pub timeout: Duration,
+    pub current_wait: Duration,
+    pub message: String,
+    pub request_statistics: Arc<AtomicUsize>,
+    pub response_time_acc: Arc<AtomicUsize>,
+}
+
+impl WebSocketClientTarget {
+    pub(crate) fn new(target_config: &TargetConfig, statistics: &TargetStatistics) -> Self {
+        trace!(
+            "Creating WebSocket client with target: {}",
+            target_config.target
+        );
+
+        Self {
+            target: target_config.target.clone(),
+            timeout: Duration::from_millis(target_config.client_timeout),
+            current_wait: Duration::from_millis(target_config.client_wait_start),
+            message: "x".repeat(target_config.message_size),
+            request_statistics: statistics.requests.clone(),
+            response_time_acc: statistics.response_time_acc.clone(),
+        }
+    }
+
+    pub(crate) async fn run_client(self) -> Result<ClientExit, ClientTargetError> {
+        let (mut socket, _) = timeout(self.timeout, connect_async(self.target.as_str()))
+            .await
+            .map_err(|_| ClientTargetError::WebSocketFailure("connection timeout".to_owned()))?
+            .map_err(|e| ClientTargetError::WebSocketFailure(e.to_string()))?;
+
+        loop {
+            let request_start_time = Instant::now();
+
+            socket
+                .send(Message::text(self.message.as_str()))
+                .await
+                .map_err(|e| ClientTargetError::WebSocketFailure(e.to_string()))?;
+
+            loop {
+                match timeout(self.timeout, socket.next()).await {
+                    Ok(Some(Ok(Message::Text(_) | Message::Binary(_)))) => break,
+                    // The target closing the connection, e.g. after a message limit, is not a
+                    // failure. The client is replaced with a fresh connection.
+                    Ok(Some(Ok(Message::Close(_)))) | Ok(None) => return Ok(ClientExit::Closed),
+                    Ok(Some(Ok(_))) => continue,
+                    Ok(Some(Err(e))) => Err(ClientTargetError::WebSocketFailure(e.to_string()))?,
+                    Err(_) => Err(ClientTargetError::WebSocketFailure(
+                        "message timeout".to_owned(),
+                    ))?,
+                }
+            }
+
+            self.request_statistics
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            self.response_time_acc.fetch_add(
+                request_start_time.elapsed().as_millis() as usize,
+                std::sync::atomic::Ordering::Relaxed,
+            );
+
+            sleep(self.current_wait).await
+        }
+    }
+}
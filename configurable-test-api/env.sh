@@ -10,3 +10,10 @@ export APP_ROUTES_APPLIST_PATH="/apps"
 export APP_ROUTES_APPLIST_METHOD="GET"
 export APP_ROUTES_APPGET_PATH="/app/{id}"
 export APP_ROUTES_APPGET_METHOD="GET"
+export APP_ROUTES_APPGET_BODY="app {{path.id}}"
+export APP_ROUTES_APPGET_RESPONSES_MISSING_WHEN_PATH_ID="^0$"
+export APP_ROUTES_APPGET_RESPONSES_MISSING_STATUS="404"
+export APP_ROUTES_ITEMS_PATH="/items"
+export APP_ROUTES_ITEMS_KIND="RESOURCE"
+export APP_ROUTES_ITEMS_RESOURCE_CAPACITY="10000"
+export APP_JOURNAL_ENABLED="true"
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use axum::http::HeaderMap;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::config::{JsonMatchConfig, MatchConfig};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum MatcherError {
+    #[error("Invalid pattern: {0}, due to error: {1}")]
+    InvalidPattern(String, regex::Error),
+}
+
+fn compile(pattern: &str) -> Result<Regex, MatcherError> {
+    Regex::new(pattern).map_err(|e| MatcherError::InvalidPattern(pattern.to_owned(), e))
+}
+
+fn compile_all(patterns: &HashMap<String, String>) -> Result<Vec<(String, Regex)>, MatcherError> {
+    patterns
+        .iter()
+        .map(|(k, v)| compile(v).map(|r| (k.to_owned(), r)))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct JsonMatcher {
+    pointer: String,
+    value: Regex,
+}
+
+/// Compiled form of [`MatchConfig`].
+#[derive(Debug, Clone)]
+pub(crate) struct RequestMatcher {
+    path: Vec<(String, Regex)>,
+    query: Vec<(String, Regex)>,
+    header: Vec<(String, Regex)>,
+    body: Option<Regex>,
+    json: Option<JsonMatcher>,
+}
+
+impl TryFrom<&MatchConfig> for RequestMatcher {
+    type Error = MatcherError;
+
+    fn try_from(value: &MatchConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            path: compile_all(&value.path)?,
+            query: compile_all(&value.query)?,
+            header: compile_all(&value.header)?
+                .into_iter()
+                .map(|(k, v)| (k.to_ascii_lowercase(), v))
+                .collect(),
+            body: value.body.as_deref().map(compile).transpose()?,
+            json: value
+                .json
+                .as_ref()
+                .map(|JsonMatchConfig { pointer, value }| {
+                    compile(value).map(|value| JsonMatcher {
+                        pointer: pointer.clone(),
+                        value,
+                    })
+                })
+                .transpose()?,
+        })
+    }
+}
+
+impl RequestMatcher {
+    pub(crate) fn matches(
+        &self,
+        path: &HashMap<String, String>,
+        query: &HashMap<String, String>,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> bool {
+        self.path
+            .iter()
+            .all(|(k, r)| path.get(k).is_some_and(|v| r.is_match(v)))
+            && self
+                .query
+                .iter()
+                .all(|(k, r)| query.get(k).is_some_and(|v| r.is_match(v)))
+            && self.header.iter().all(|(k, r)| {
+                headers
+                    .get_all(k)
+                    .iter()
+                    .filter_map(|v| v.to_str().ok())
+                    .any(|v| r.is_match(v))
+            })
+            && self
+                .body
+                .as_ref()
+                .is_none_or(|r| r.is_match(&String::from_utf8_lossy(body)))
+            && self.json.as_ref().is_none_or(|m| {
+                serde_json::from_slice::<Value>(body)
+                    .ok()
+                    .and_then(|v| {
+                        v.pointer(&m.pointer).map(|v| match v {
+                            Value::String(s) => m.value.is_match(s),
+                            other => m.value.is_match(&other.to_string()),
+                        })
+                    })
+                    .unwrap_or(false)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(config: serde_json::Value) -> Result<RequestMatcher, MatcherError> {
+        RequestMatcher::try_from(&serde_json::from_value::<MatchConfig>(config).unwrap())
+    }
+
+    fn matches(config: serde_json::Value, headers: &[(&str, &str)], body: &str) -> bool {
+        let path = HashMap::from([("id".to_owned(), "42".to_owned())]);
+        let query = HashMap::from([("debug".to_owned(), "true".to_owned())]);
+        let headers = headers
+            .iter()
+            .map(|(k, v)| (k.parse().unwrap(), v.parse().unwrap()))
+            .collect::<HeaderMap>();
+
+        matcher(config)
+            .unwrap()
+            .matches(&path, &query, &headers, body.as_bytes())
+    }
+
+    #[test]
+    fn empty_matcher_matches_everything() {
+        assert!(matches(serde_json::json!({}), &[], ""));
+    }
+
+    #[test]
+    fn matches_path_query_and_body() {
+        assert!(matches(serde_json::json!({"path": {"id": "^4"}}), &[], ""));
+        assert!(!matches(serde_json::json!({"path": {"id": "^5"}}), &[], ""));
+        assert!(!matches(
+            serde_json::json!({"path": {"other": ".*"}}),
+            &[],
+            ""
+        ));
+
+        assert!(matches(
+            serde_json::json!({"query": {"debug": "true"}}),
+            &[],
+            ""
+        ));
+        assert!(!matches(
+            serde_json::json!({"query": {"debug": "false"}}),
+            &[],
+            ""
+        ));
+
+        assert!(matches(
+            serde_json::json!({"body": "hello"}),
+            &[],
+            "say hello"
+        ));
+        assert!(!matches(
+            serde_json::json!({"body": "^hello"}),
+            &[],
+            "say hello"
+        ));
+    }
+
+    #[test]
+    fn matches_any_value_of_a_header_regardless_of_name_case() {
+        let config = serde_json::json!({"header": {"X-Tenant": "^b$"}});
+
+        assert!(matches(
+            config.clone(),
+            &[("x-tenant", "a"), ("x-tenant", "b")],
+            ""
+        ));
+        assert!(!matches(config, &[("x-tenant", "a")], ""));
+    }
+
+    #[test]
+    fn matches_json_values_by_pointer() {
+        let config = |value| serde_json::json!({"json": {"pointer": "/user/id", "value": value}});
+
+        assert!(matches(config("^7$"), &[], r#"{"user": {"id": 7}}"#));
+        assert!(matches(
+            config("^seven$"),
+            &[],
+            r#"{"user": {"id": "seven"}}"#
+        ));
+        assert!(!matches(config(".*"), &[], r#"{"user": {}}"#));
+        assert!(!matches(config(".*"), &[], "not json"));
+    }
+
+    #[test]
+    fn requires_every_matcher_to_pass() {
+        let config = serde_json::json!({"path": {"id": "42"}, "query": {"debug": "false"}});
+
+        assert!(!matches(config, &[], ""));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(matches!(
+            matcher(serde_json::json!({"query": {"q": "("}})),
+            Err(MatcherError::InvalidPattern(..))
+        ));
+    }
+}
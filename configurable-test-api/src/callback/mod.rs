@@ -0,0 +1,264 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use axum::{
+    body::Bytes,
+    extract::{Query, RawPathParams},
+    http::{
+        HeaderMap, HeaderName, HeaderValue, StatusCode,
+        header::{InvalidHeaderName, InvalidHeaderValue},
+        request::Parts,
+        status::InvalidStatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::{MethodFilter, MethodRouter, on},
+};
+use shared::Method;
+use tokio::time::sleep;
+
+use crate::{
+    callback::{
+        matcher::{MatcherError, RequestMatcher},
+        template::{Template, TemplateContext, TemplateError},
+    },
+    config::{ResponseConfig, RouteConfig},
+    instance::protocol_name,
+};
+
+mod matcher;
+mod template;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum MakeCallbackError {
+    #[error("Invalid status code: {0}")]
+    Status(#[from] InvalidStatusCode),
+    #[error("Invalid header name: {0}")]
+    HeaderName(#[from] InvalidHeaderName),
+    #[error("Invalid header value: {0}")]
+    HeaderValue(#[from] InvalidHeaderValue),
+    #[error("Invalid body template: {0}")]
+    Template(#[from] TemplateError),
+    #[error("Invalid matcher: {0}")]
+    Matcher(#[from] MatcherError),
+}
+
+fn make_headers(headers: &HashMap<String, String>) -> Result<HeaderMap, MakeCallbackError> {
+    headers
+        .iter()
+        .map(|(k, v)| Ok((HeaderName::try_from(k)?, HeaderValue::try_from(v)?)))
+        .collect()
+}
+
+#[derive(Debug)]
+struct RouteResponse {
+    matcher: Option<RequestMatcher>,
+    latency: u64,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Template,
+}
+
+impl RouteResponse {
+    fn try_from_response_config(
+        response: &ResponseConfig,
+        route: &RouteConfig,
+        default_body: &str,
+    ) -> Result<Self, MakeCallbackError> {
+        let mut headers = make_headers(&route.headers)?;
+        headers.extend(make_headers(&response.headers)?);
+
+        Ok(Self {
+            matcher: Some(RequestMatcher::try_from(&response.when)?),
+            latency: response.latency.unwrap_or(route.latency),
+            status: StatusCode::from_u16(response.status)?,
+            headers,
+            body: Template::try_from(
+                response
+                    .body
+                    .as_deref()
+                    .or(route.body.as_deref())
+                    .unwrap_or(default_body),
+            )?,
+        })
+    }
+
+    fn try_from_route_config(
+        route: &RouteConfig,
+        default_body: &str,
+    ) -> Result<Self, MakeCallbackError> {
+        Ok(Self {
+            matcher: None,
+            latency: route.latency,
+            status: StatusCode::from_u16(route.status)?,
+            headers: make_headers(&route.headers)?,
+            body: Template::try_from(route.body.as_deref().unwrap_or(default_body))?,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct RouteResponses {
+    conditional: Vec<RouteResponse>,
+    fallback: RouteResponse,
+    instance: String,
+}
+
+impl RouteResponses {
+    /// The first conditional response matching the request, otherwise the fallback.
+    fn select(
+        &self,
+        path: &HashMap<String, String>,
+        query: &HashMap<String, String>,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> &RouteResponse {
+        self.conditional
+            .iter()
+            .find(|r| {
+                r.matcher
+                    .as_ref()
+                    .is_none_or(|m| m.matches(path, query, headers, body))
+            })
+            .unwrap_or(&self.fallback)
+    }
+
+    async fn respond(
+        &self,
+        params: RawPathParams,
+        query: HashMap<String, String>,
+        Parts {
+            method,
+            uri,
+            version,
+            headers,
+            ..
+        }: Parts,
+        body: Bytes,
+    ) -> Response {
+        log::debug!("Received request, method: {method}, uri: {uri}");
+
+        let path = params
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect::<HashMap<_, _>>();
+
+        let response = self.select(&path, &query, &headers, &body);
+
+        sleep(Duration::from_millis(response.latency)).await;
+
+        let body = response.body.render(&TemplateContext {
+            path: &path,
+            query: &query,
+            headers: &headers,
+            method: &method,
+            uri: &uri.to_string(),
+            protocol: protocol_name(version),
+            instance: &self.instance,
+        });
+
+        (response.status, response.headers.clone(), body).into_response()
+    }
+}
+
+pub(crate) fn method_filter(method: Method) -> MethodFilter {
+    match method {
+        Method::Options => MethodFilter::OPTIONS,
+        Method::Get => MethodFilter::GET,
+        Method::Post => MethodFilter::POST,
+        Method::Put => MethodFilter::PUT,
+        Method::Delete => MethodFilter::DELETE,
+        Method::Head => MethodFilter::HEAD,
+        Method::Trace => MethodFilter::TRACE,
+        Method::Connect => MethodFilter::CONNECT,
+        Method::Patch => MethodFilter::PATCH,
+    }
+}
+
+impl RouteResponses {
+    fn try_from_route_config(
+        route: &RouteConfig,
+        default_body: &str,
+        instance: &str,
+    ) -> Result<Self, MakeCallbackError> {
+        Ok(Self {
+            conditional: route
+                .responses
+                .values()
+                .map(|r| RouteResponse::try_from_response_config(r, route, default_body))
+                .collect::<Result<_, _>>()?,
+            fallback: RouteResponse::try_from_route_config(route, default_body)?,
+            instance: instance.to_owned(),
+        })
+    }
+}
+
+pub fn make_callback<S>(
+    route: &RouteConfig,
+    default_body: &str,
+    instance: &str,
+) -> Result<MethodRouter<S>, MakeCallbackError>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let responses = Arc::new(RouteResponses::try_from_route_config(
+        route,
+        default_body,
+        instance,
+    )?);
+
+    let handler =
+        async move |params: RawPathParams,
+                    Query(query): Query<HashMap<String, String>>,
+                    parts: Parts,
+                    body: Bytes| { responses.respond(params, query, parts, body).await };
+
+    Ok(on(method_filter(route.method), handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn select_status(route: serde_json::Value, query: &[(&str, &str)]) -> u16 {
+        let route = serde_json::from_value::<RouteConfig>(route).unwrap();
+        let responses = RouteResponses::try_from_route_config(&route, "", "api-1").unwrap();
+        let query = query
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        responses
+            .select(&HashMap::new(), &query, &HeaderMap::new(), &[])
+            .status
+            .as_u16()
+    }
+
+    #[test]
+    fn first_matching_response_by_name_wins() {
+        let route = serde_json::json!({
+            "path": "/items",
+            "status": 200,
+            "responses": {
+                "b-any-mode": {"when": {"query": {"mode": ".*"}}, "status": 202},
+                "a-slow-mode": {"when": {"query": {"mode": "^slow$"}}, "status": 201},
+                "c-always": {"status": 203},
+            },
+        });
+
+        assert_eq!(select_status(route.clone(), &[("mode", "slow")]), 201);
+        assert_eq!(select_status(route.clone(), &[("mode", "fast")]), 202);
+        assert_eq!(select_status(route, &[]), 203);
+    }
+
+    #[test]
+    fn falls_back_to_the_route_response() {
+        let route = serde_json::json!({
+            "path": "/items",
+            "status": 200,
+            "responses": {
+                "slow": {"when": {"query": {"mode": "^slow$"}}, "status": 201},
+            },
+        });
+
+        assert_eq!(select_status(route, &[("mode", "fast")]), 200);
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use axum::http::{HeaderMap, Method};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum TemplateError {
+    #[error("Unterminated placeholder in template: {0}")]
+    Unterminated(String),
+    #[error("Unknown placeholder: {0}")]
+    UnknownPlaceholder(String),
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Path(String),
+    Query(String),
+    Header(String),
+    Method,
+    Uri,
+    Protocol,
+    Instance,
+}
+
+/// Values available to a template when rendering a response.
+pub(crate) struct TemplateContext<'a> {
+    pub path: &'a HashMap<String, String>,
+    pub query: &'a HashMap<String, String>,
+    pub headers: &'a HeaderMap,
+    pub method: &'a Method,
+    pub uri: &'a str,
+    pub protocol: &'a str,
+    pub instance: &'a str,
+}
+
+/// Response body template supporting `{{path.<name>}}`, `{{query.<name>}}`,
+/// `{{header.<name>}}`, `{{method}}`, `{{uri}}`, `{{protocol}}` and `{{instance}}`
+/// placeholders. Missing values render as an empty string.
+#[derive(Debug, Clone)]
+pub(crate) struct Template {
+    segments: Vec<Segment>,
+}
+
+impl TryFrom<&str> for Template {
+    type Error = TemplateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut segments = Vec::new();
+        let mut remaining = value;
+
+        while let Some(start) = remaining.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Literal(remaining[..start].to_owned()));
+            }
+
+            let end = remaining[start..]
+                .find("}}")
+                .ok_or(TemplateError::Unterminated(value.to_owned()))?;
+            let placeholder = remaining[start + 2..start + end].trim();
+
+            segments.push(match placeholder.split_once(".") {
+                Some(("path", name)) => Segment::Path(name.to_owned()),
+                Some(("query", name)) => Segment::Query(name.to_owned()),
+                Some(("header", name)) => Segment::Header(name.to_ascii_lowercase()),
+                None if placeholder == "method" => Segment::Method,
+                None if placeholder == "uri" => Segment::Uri,
+                None if placeholder == "protocol" => Segment::Protocol,
+                None if placeholder == "instance" => Segment::Instance,
+                _ => Err(TemplateError::UnknownPlaceholder(placeholder.to_owned()))?,
+            });
+
+            remaining = &remaining[start + end + 2..];
+        }
+
+        if !remaining.is_empty() {
+            segments.push(Segment::Literal(remaining.to_owned()));
+        }
+
+        Ok(Self { segments })
+    }
+}
+
+impl Template {
+    pub(crate) fn render(&self, context: &TemplateContext) -> String {
+        self.segments
+            .iter()
+            .fold(String::new(), |mut acc, segment| {
+                match segment {
+                    Segment::Literal(l) => acc.push_str(l),
+                    Segment::Path(name) => {
+                        acc.push_str(context.path.get(name).map_or("", String::as_str))
+                    }
+                    Segment::Query(name) => {
+                        acc.push_str(context.query.get(name).map_or("", String::as_str))
+                    }
+                    Segment::Header(name) => acc.push_str(
+                        context
+                            .headers
+                            .get(name)
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or(""),
+                    ),
+                    Segment::Method => acc.push_str(context.method.as_str()),
+                    Segment::Uri => acc.push_str(context.uri),
+                    Segment::Protocol => acc.push_str(context.protocol),
+                    Segment::Instance => acc.push_str(context.instance),
+                }
+
+                acc
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> String {
+        let path = HashMap::from([("id".to_owned(), "42".to_owned())]);
+        let query = HashMap::from([("q".to_owned(), "rust".to_owned())]);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-request-id", "abc".parse().unwrap());
+
+        Template::try_from(template)
+            .unwrap()
+            .render(&TemplateContext {
+                path: &path,
+                query: &query,
+                headers: &headers,
+                method: &Method::POST,
+                uri: "/items/42?q=rust",
+                protocol: "HTTP/1.1",
+                instance: "api-1",
+            })
+    }
+
+    #[test]
+    fn renders_every_placeholder() {
+        assert_eq!(
+            render(
+                "{{method}} {{uri}} {{protocol}} on {{instance}}: id={{path.id}} q={{ query.q }} request={{header.X-Request-Id}}"
+            ),
+            "POST /items/42?q=rust HTTP/1.1 on api-1: id=42 q=rust request=abc"
+        );
+    }
+
+    #[test]
+    fn renders_literals_and_missing_values() {
+        assert_eq!(render("plain } text {"), "plain } text {");
+        assert_eq!(render(""), "");
+        assert_eq!(
+            render("[{{path.missing}}][{{query.missing}}][{{header.missing}}]"),
+            "[][][]"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        for template in ["{{cookie.id}}", "{{path}}", "{{nope}}", "{{}}"] {
+            assert!(
+                matches!(
+                    Template::try_from(template),
+                    Err(TemplateError::UnknownPlaceholder(_))
+                ),
+                "{template}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unterminated_placeholders() {
+        for template in ["{{path.id", "before {{method} after", "{{uri}} {{"] {
+            assert!(
+                matches!(
+                    Template::try_from(template),
+                    Err(TemplateError::Unterminated(_))
+                ),
+                "{template}"
+            );
+        }
+    }
+}
@@ -1,40 +1,1000 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    fmt::Display,
+    num::NonZeroU32,
+    path::Path,
+};
 
-use serde::Deserialize;
-use shared::Method;
+use figment::{
+    Figment,
+    providers::{Env, Format, Json, Toml, Yaml},
+};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use shared::{Method, grpc};
 
 fn default_port() -> u16 {
     8080
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct AppConfig {
     #[serde(default = "default_port")]
     pub port: u16,
+    #[serde(default)]
     pub routes: HashMap<String, RouteConfig>,
+    #[serde(default)]
+    pub journal: JournalConfig,
+    #[serde(default)]
+    pub instance: InstanceConfig,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default = "default_protocol")]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub grpc: GrpcConfig,
+    /// Limits shared by every route and the gRPC service of this instance.
+    #[serde(default)]
+    pub limit: LimitConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+}
+
+fn default_health_enabled() -> bool {
+    false
+}
+
+fn default_health_live() -> String {
+    "/livez".to_owned()
+}
+
+fn default_health_ready() -> String {
+    "/readyz".to_owned()
+}
+
+/// Built-in liveness and readiness endpoints. The instance starts in a starting state for the
+/// slow-start delay, then reports healthy until one of the configured triggers moves it to a
+/// degraded or unhealthy state.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct HealthConfig {
+    #[serde(default = "default_health_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_health_live")]
+    pub live: String,
+    #[serde(default = "default_health_ready")]
+    pub ready: String,
+    /// Slow-start delay in milliseconds during which the instance is not ready.
+    #[serde(default)]
+    pub start: u64,
+    #[serde(default)]
+    pub unhealthy: HealthTriggerConfig,
+    #[serde(default)]
+    pub degraded: DegradedConfig,
+    #[serde(default)]
+    pub flap: FlapConfig,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_health_enabled(),
+            live: default_health_live(),
+            ready: default_health_ready(),
+            start: 0,
+            unhealthy: Default::default(),
+            degraded: Default::default(),
+            flap: Default::default(),
+        }
+    }
+}
+
+impl Display for HealthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            format!(
+                "{{enabled: \"{}\", live: \"{}\", ready: \"{}\", start: \"{}\", flap: \"{}/{}\"}}",
+                self.enabled, self.live, self.ready, self.start, self.flap.up, self.flap.down,
+            )
+            .as_str(),
+        )
+    }
+}
+
+/// Moves the instance into a state once either threshold is reached.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct HealthTriggerConfig {
+    /// Number of requests served by the routes.
+    pub requests: Option<u64>,
+    /// Time in milliseconds since startup.
+    pub after: Option<u64>,
+}
+
+/// Degraded instances stay ready but respond with a custom status and body.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct DegradedConfig {
+    /// Number of requests served by the routes.
+    pub requests: Option<u64>,
+    /// Time in milliseconds since startup.
+    pub after: Option<u64>,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    pub body: Option<String>,
+}
+
+impl Default for DegradedConfig {
+    fn default() -> Self {
+        Self {
+            requests: None,
+            after: None,
+            status: default_status(),
+            body: None,
+        }
+    }
+}
+
+/// Alternates between healthy for `up` and unhealthy for `down` milliseconds once started.
+/// Disabled while `down` is zero.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct FlapConfig {
+    #[serde(default)]
+    pub up: u64,
+    #[serde(default)]
+    pub down: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ConfigError {
+    #[error("Unsupported config file: {0}, expected a .yaml, .yml, .toml or .json file")]
+    FileFormat(String),
+    #[error(transparent)]
+    Figment(#[from] Box<figment::Error>),
+}
+
+impl AppConfig {
+    /// Reads the optional config file, with `APP_` environment variables taking precedence over
+    /// its values.
+    pub(crate) fn load(file: Option<&Path>) -> Result<Self, ConfigError> {
+        let mut figment = Figment::new();
+
+        if let Some(file) = file {
+            figment = match file.extension().and_then(OsStr::to_str) {
+                Some("yaml" | "yml") => figment.merge(Yaml::file_exact(file)),
+                Some("toml") => figment.merge(Toml::file_exact(file)),
+                Some("json") => figment.merge(Json::file_exact(file)),
+                _ => Err(ConfigError::FileFormat(file.display().to_string()))?,
+            };
+        }
+
+        Ok(figment
+            .merge(Env::prefixed("APP_").split("_"))
+            .extract()
+            .map_err(Box::new)?)
+    }
+}
+
+fn default_shutdown_lame() -> u64 {
+    0
+}
+
+fn default_shutdown_drain() -> u64 {
+    10000
+}
+
+/// Graceful shutdown on SIGINT or SIGTERM. The instance first enters a lame duck period in which
+/// the health route fails while every other route keeps serving, then stops accepting connections
+/// and waits for in-flight requests to complete.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ShutdownConfig {
+    /// Length of the lame duck period in milliseconds.
+    #[serde(default = "default_shutdown_lame")]
+    pub lame: u64,
+    /// Time in milliseconds allowed for open connections to drain before they are closed.
+    #[serde(default = "default_shutdown_drain")]
+    pub drain: u64,
+    /// Name of the route responding with `503` once shutdown has started.
+    pub health: Option<String>,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            lame: default_shutdown_lame(),
+            drain: default_shutdown_drain(),
+            health: None,
+        }
+    }
+}
+
+impl Display for ShutdownConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            format!(
+                "{{lame: \"{}\", drain: \"{}\", health: \"{}\"}}",
+                self.lame,
+                self.drain,
+                self.health.as_deref().unwrap_or("none"),
+            )
+            .as_str(),
+        )
+    }
+}
+
+/// HTTP versions the listener accepts. HTTP/2 is negotiated with ALPN over TLS, or with prior
+/// knowledge (h2c) over plain TCP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum Protocol {
+    #[serde(alias = "AUTO")]
+    Auto,
+    #[serde(alias = "HTTP1")]
+    Http1,
+    #[serde(alias = "HTTP2")]
+    Http2,
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "AUTO",
+            Self::Http1 => "HTTP1",
+            Self::Http2 => "HTTP2",
+        })
+    }
+}
+
+fn default_protocol() -> Protocol {
+    Protocol::Auto
+}
+
+fn default_tls_enabled() -> bool {
+    false
+}
+
+/// TLS settings for the listener. When enabled without a certificate and key a self-signed
+/// certificate is generated at startup.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct TlsConfig {
+    #[serde(default = "default_tls_enabled")]
+    pub enabled: bool,
+    /// Path to a PEM encoded certificate chain.
+    pub cert: Option<String>,
+    /// Path to a PEM encoded private key.
+    pub key: Option<String>,
+    /// Path to a PEM encoded CA bundle, client certificates are required and verified against it
+    /// when set.
+    pub ca: Option<String>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_tls_enabled(),
+            cert: None,
+            key: None,
+            ca: None,
+        }
+    }
+}
+
+impl Display for TlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            format!(
+                "{{enabled: \"{}\", cert: \"{}\", key: \"{}\", ca: \"{}\"}}",
+                self.enabled,
+                self.cert.as_deref().unwrap_or("self-signed"),
+                self.key.as_deref().unwrap_or("self-signed"),
+                self.ca.as_deref().unwrap_or("none"),
+            )
+            .as_str(),
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum RouteTableError {
+    #[error("No routes configured")]
+    Empty,
+    #[error("Path: {1} for route: {0} must start with '/'")]
+    InvalidPath(String, String),
+    #[error("Routes: {0} and {1} both handle {2} {3}")]
+    DuplicateMethod(String, String, Method, String),
+    #[error("Routes: {0} ({1}) and {2} ({3}) have conflicting path parameters")]
+    ConflictingParameters(String, String, String, String),
+    #[error("Shutdown health route: {0} is not configured")]
+    UnknownHealthRoute(String),
+}
+
+/// Replaces path parameter names so that paths which only differ in parameter names, and would
+/// therefore be ambiguous to the router, compare equal.
+fn path_shape(path: &str) -> String {
+    path.split("/")
+        .map(|segment| match segment.strip_prefix("{") {
+            Some(s) if s.starts_with("*") => "{*}",
+            Some(_) => "{}",
+            None => segment,
+        })
+        .join("/")
+}
+
+impl AppConfig {
+    /// Checks the whole route table for paths and methods that cannot be registered together.
+    pub(crate) fn validate_routes(&self) -> Result<(), RouteTableError> {
+        if self.routes.is_empty() && !self.grpc.enabled && !self.health.enabled {
+            Err(RouteTableError::Empty)?;
+        }
+
+        if let Some(health) = &self.shutdown.health
+            && !self.routes.contains_key(health)
+        {
+            Err(RouteTableError::UnknownHealthRoute(health.clone()))?;
+        }
+
+        let mut endpoints: HashMap<(String, Method), (&str, String)> = HashMap::new();
+        let mut shapes: HashMap<String, (&str, String)> = HashMap::new();
+
+        if self.journal.enabled {
+            for method in [Method::Get, Method::Delete] {
+                endpoints.insert(
+                    (self.journal.path.clone(), method),
+                    ("journal", self.journal.path.clone()),
+                );
+            }
+            shapes.insert(
+                path_shape(&self.journal.path),
+                ("journal", self.journal.path.clone()),
+            );
+        }
+
+        if self.health.enabled {
+            for path in [&self.health.live, &self.health.ready] {
+                endpoints.insert((path.clone(), Method::Get), ("health", path.clone()));
+                shapes.insert(path_shape(path), ("health", path.clone()));
+            }
+        }
+
+        if self.grpc.enabled {
+            for path in [grpc::UNARY_PATH, grpc::SERVER_STREAM_PATH] {
+                endpoints.insert((path.to_owned(), Method::Post), ("grpc", path.to_owned()));
+                shapes.insert(path_shape(path), ("grpc", path.to_owned()));
+            }
+        }
+
+        for (name, route) in self.routes.iter().sorted_by_key(|(name, _)| *name) {
+            for (path, method) in route.endpoints() {
+                if !path.starts_with("/") {
+                    Err(RouteTableError::InvalidPath(name.clone(), path.clone()))?;
+                }
+
+                match shapes.get(&path_shape(&path)) {
+                    Some((other, other_path)) if *other_path != path => {
+                        Err(RouteTableError::ConflictingParameters(
+                            other.to_string(),
+                            other_path.clone(),
+                            name.clone(),
+                            path.clone(),
+                        ))?
+                    }
+                    Some(_) => (),
+                    None => {
+                        shapes.insert(path_shape(&path), (name, path.clone()));
+                    }
+                }
+
+                if let Some((other, _)) = endpoints.get(&(path.clone(), method)) {
+                    Err(RouteTableError::DuplicateMethod(
+                        other.to_string(),
+                        name.clone(),
+                        method,
+                        path.clone(),
+                    ))?;
+                }
+
+                endpoints.insert((path.clone(), method), (name, path));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// CPU and memory work performed for each request before it is handled, on the blocking thread
+/// pool so the runtime is not stalled.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct WorkConfig {
+    /// Number of chained hash iterations computed.
+    #[serde(default)]
+    pub hashes: u64,
+    /// Time in microseconds spent busy waiting.
+    #[serde(default)]
+    pub spin: u64,
+    /// Kilobytes allocated and written.
+    #[serde(default)]
+    pub memory: usize,
+    /// Number of the most recent allocations kept alive per route.
+    #[serde(default)]
+    pub retain: usize,
+}
+
+impl WorkConfig {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.hashes > 0 || self.spin > 0 || self.memory > 0
+    }
+}
+
+impl Display for WorkConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            format!(
+                "{{hashes: \"{}\", spin: \"{}\", memory: \"{}\", retain: \"{}\"}}",
+                self.hashes, self.spin, self.memory, self.retain,
+            )
+            .as_str(),
+        )
+    }
+}
+
+fn default_limit_timeout() -> u64 {
+    1000
+}
+
+/// Backpressure applied to requests. Requests over the rate limit are rejected with `429` and a
+/// `Retry-After` header, requests over the concurrency limit wait in a bounded queue and are
+/// rejected with `503` when it is full or they time out.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct LimitConfig {
+    /// Maximum number of requests handled at once.
+    pub concurrency: Option<usize>,
+    /// Number of requests allowed to wait for a free slot once the concurrency limit is reached.
+    #[serde(default)]
+    pub queue: usize,
+    /// Time in milliseconds a request may wait in the queue.
+    #[serde(default = "default_limit_timeout")]
+    pub timeout: u64,
+    /// Requests per second admitted by the token bucket.
+    pub rate: Option<NonZeroU32>,
+    /// Token bucket size, defaults to one second of requests.
+    pub burst: Option<NonZeroU32>,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: None,
+            queue: 0,
+            timeout: default_limit_timeout(),
+            rate: None,
+            burst: None,
+        }
+    }
+}
+
+impl LimitConfig {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.concurrency.is_some() || self.rate.is_some()
+    }
+}
+
+impl Display for LimitConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unlimited = || "unlimited".to_owned();
+
+        f.write_str(
+            format!(
+                "{{concurrency: \"{}\", queue: \"{}\", timeout: \"{}\", rate: \"{}\", burst: \"{}\"}}",
+                self.concurrency.map_or_else(unlimited, |c| c.to_string()),
+                self.queue,
+                self.timeout,
+                self.rate.map_or_else(unlimited, |r| r.to_string()),
+                self.burst.or(self.rate).map_or_else(unlimited, |b| b.to_string()),
+            )
+            .as_str(),
+        )
+    }
+}
+
+fn default_grpc_enabled() -> bool {
+    false
+}
+
+fn default_grpc_code() -> i32 {
+    0
+}
+
+fn default_grpc_count() -> u32 {
+    10
+}
+
+fn default_grpc_delay() -> u64 {
+    100
+}
+
+/// Settings for the built-in `testapi.Echo` gRPC service, served alongside the routes on the same
+/// listener. gRPC requires HTTP/2, so the protocol must not be restricted to HTTP/1.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct GrpcConfig {
+    #[serde(default = "default_grpc_enabled")]
+    pub enabled: bool,
+    /// Latency in milliseconds before responding, or before the first streamed message.
+    #[serde(default)]
+    pub latency: u64,
+    /// gRPC status code returned by every call, `0` (OK) echoes the request.
+    #[serde(default = "default_grpc_code")]
+    pub code: i32,
+    /// Status message sent with a non-OK code.
+    #[serde(default)]
+    pub message: String,
+    /// Number of messages streamed by `ServerStream` when the request does not set a count.
+    #[serde(default = "default_grpc_count")]
+    pub count: u32,
+    /// Delay in milliseconds between streamed messages.
+    #[serde(default = "default_grpc_delay")]
+    pub delay: u64,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_grpc_enabled(),
+            latency: 0,
+            code: default_grpc_code(),
+            message: String::new(),
+            count: default_grpc_count(),
+            delay: default_grpc_delay(),
+        }
+    }
+}
+
+impl Display for GrpcConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            format!(
+                "{{enabled: \"{}\", latency: \"{}\", code: \"{}\", count: \"{}\", delay: \"{}\"}}",
+                self.enabled, self.latency, self.code, self.count, self.delay,
+            )
+            .as_str(),
+        )
+    }
+}
+
+fn default_instance_id() -> String {
+    std::env::var("HOSTNAME").unwrap_or("unknown".to_owned())
+}
+
+fn default_instance_header() -> String {
+    "x-instance-id".to_owned()
+}
+
+fn default_instance_body() -> bool {
+    false
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct InstanceConfig {
+    #[serde(default = "default_instance_id")]
+    pub id: String,
+    #[serde(default = "default_instance_header")]
+    pub header: String,
+    #[serde(default = "default_instance_body")]
+    pub body: bool,
+}
+
+impl Default for InstanceConfig {
+    fn default() -> Self {
+        Self {
+            id: default_instance_id(),
+            header: default_instance_header(),
+            body: default_instance_body(),
+        }
+    }
+}
+
+impl Display for InstanceConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            format!(
+                "{{id: \"{}\", header: \"{}\", body: \"{}\"}}",
+                self.id, self.header, self.body,
+            )
+            .as_str(),
+        )
+    }
+}
+
+fn default_journal_enabled() -> bool {
+    false
+}
+
+fn default_journal_path() -> String {
+    "/__journal".to_owned()
+}
+
+fn default_journal_capacity() -> usize {
+    10000
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct JournalConfig {
+    #[serde(default = "default_journal_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_journal_path")]
+    pub path: String,
+    #[serde(default = "default_journal_capacity")]
+    pub capacity: usize,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_journal_enabled(),
+            path: default_journal_path(),
+            capacity: default_journal_capacity(),
+        }
+    }
+}
+
+impl Display for JournalConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            format!(
+                "{{enabled: \"{}\", path: \"{}\", capacity: \"{}\"}}",
+                self.enabled, self.path, self.capacity,
+            )
+            .as_str(),
+        )
+    }
 }
 
 fn default_method() -> Method {
     Method::Get
 }
 
-#[derive(Debug, Deserialize)]
+fn default_status() -> u16 {
+    200
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum RouteKind {
+    #[serde(alias = "STATIC")]
+    Static,
+    #[serde(alias = "RESOURCE")]
+    Resource,
+    #[serde(alias = "STREAM")]
+    Stream,
+    #[serde(alias = "EVENTS")]
+    Events,
+    #[serde(alias = "DOWNLOAD")]
+    Download,
+    #[serde(alias = "WEBSOCKET")]
+    WebSocket,
+}
+
+impl Display for RouteKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Static => "STATIC",
+            Self::Resource => "RESOURCE",
+            Self::Stream => "STREAM",
+            Self::Events => "EVENTS",
+            Self::Download => "DOWNLOAD",
+            Self::WebSocket => "WEBSOCKET",
+        })
+    }
+}
+
+fn default_route_kind() -> RouteKind {
+    RouteKind::Static
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct RouteConfig {
     pub path: String,
+    #[serde(default = "default_route_kind")]
+    pub kind: RouteKind,
     #[serde(default = "default_method")]
     pub method: Method,
     #[serde(default)]
     pub latency: u64,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    /// Response body template, defaults to the instance greeting.
+    pub body: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Conditional responses, evaluated in name order. The first response whose matchers all
+    /// pass is returned, otherwise the route's own status, headers and body are used.
+    #[serde(default)]
+    pub responses: BTreeMap<String, ResponseConfig>,
+    #[serde(default)]
+    pub resource: ResourceConfig,
+    #[serde(default)]
+    pub stream: StreamConfig,
+    #[serde(default)]
+    pub download: DownloadConfig,
+    #[serde(default)]
+    pub websocket: WebSocketConfig,
+    #[serde(default)]
+    pub limit: LimitConfig,
+    #[serde(default)]
+    pub work: WorkConfig,
+}
+
+impl RouteConfig {
+    /// Every path and method pair handled by this route.
+    pub(crate) fn endpoints(&self) -> Vec<(String, Method)> {
+        match self.kind {
+            RouteKind::Static | RouteKind::Stream | RouteKind::Events | RouteKind::Download => {
+                vec![(self.path.clone(), self.method)]
+            }
+            RouteKind::WebSocket => vec![(self.path.clone(), Method::Get)],
+            RouteKind::Resource => {
+                let prefix = self.path.trim_end_matches("/");
+                let item = format!("{prefix}/{{id}}");
+
+                vec![
+                    (prefix.to_owned(), Method::Get),
+                    (prefix.to_owned(), Method::Post),
+                    (item.clone(), Method::Get),
+                    (item.clone(), Method::Put),
+                    (item.clone(), Method::Patch),
+                    (item, Method::Delete),
+                ]
+            }
+        }
+    }
 }
 
 impl Display for RouteConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(
             format!(
-                "{{path: \"{}\", method: \"{}\", latency: \"{}\"}}",
-                self.path, self.method, self.latency,
+                "{{path: \"{}\", kind: \"{}\", method: \"{}\", latency: \"{}\", status: \"{}\", responses: \"{}\"}}",
+                self.path,
+                self.kind,
+                self.method,
+                self.latency,
+                self.status,
+                self.responses.keys().join(","),
             )
             .as_str(),
         )
     }
 }
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ResponseConfig {
+    #[serde(default)]
+    pub when: MatchConfig,
+    pub latency: Option<u64>,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    pub body: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// Request matchers, every value is a regular expression which must match for the response to
+/// be selected.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct MatchConfig {
+    #[serde(default)]
+    pub path: HashMap<String, String>,
+    #[serde(default)]
+    pub query: HashMap<String, String>,
+    #[serde(default)]
+    pub header: HashMap<String, String>,
+    pub body: Option<String>,
+    pub json: Option<JsonMatchConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct JsonMatchConfig {
+    /// JSON pointer into the request body, e.g. `/user/id`.
+    pub pointer: String,
+    pub value: String,
+}
+
+/// Settings for [`RouteKind::Resource`] routes.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct ResourceConfig {
+    /// Maximum number of stored items, further creates are rejected.
+    pub capacity: Option<usize>,
+    /// Additional latency in microseconds per stored item.
+    #[serde(default)]
+    pub scale: u64,
+}
+
+fn default_stream_chunks() -> usize {
+    10
+}
+
+fn default_stream_size() -> usize {
+    1024
+}
+
+fn default_stream_delay() -> u64 {
+    100
+}
+
+/// Settings for [`RouteKind::Stream`] and [`RouteKind::Events`] routes.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct StreamConfig {
+    /// Number of chunks, or events, sent before the response completes.
+    #[serde(default = "default_stream_chunks")]
+    pub chunks: usize,
+    /// Size in bytes of each chunk, or event payload.
+    #[serde(default = "default_stream_size")]
+    pub size: usize,
+    /// Delay in milliseconds between chunks.
+    #[serde(default = "default_stream_delay")]
+    pub delay: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            chunks: default_stream_chunks(),
+            size: default_stream_size(),
+            delay: default_stream_delay(),
+        }
+    }
+}
+
+fn default_download_size() -> u64 {
+    1024 * 1024
+}
+
+/// Settings for [`RouteKind::Download`] routes.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct DownloadConfig {
+    /// Total size in bytes of the downloaded file.
+    #[serde(default = "default_download_size")]
+    pub size: u64,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            size: default_download_size(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum WebSocketMode {
+    /// Every received message is sent back to the client.
+    #[serde(alias = "ECHO")]
+    Echo,
+    /// Messages are pushed periodically to every connected client.
+    #[serde(alias = "BROADCAST")]
+    Broadcast,
+}
+
+fn default_websocket_mode() -> WebSocketMode {
+    WebSocketMode::Echo
+}
+
+fn default_websocket_interval() -> u64 {
+    1000
+}
+
+fn default_websocket_size() -> usize {
+    64
+}
+
+/// Settings for [`RouteKind::WebSocket`] routes.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct WebSocketConfig {
+    #[serde(default = "default_websocket_mode")]
+    pub mode: WebSocketMode,
+    /// Interval in milliseconds between broadcast messages.
+    #[serde(default = "default_websocket_interval")]
+    pub interval: u64,
+    /// Size in bytes of broadcast messages.
+    #[serde(default = "default_websocket_size")]
+    pub size: usize,
+    /// Number of messages sent to a client before the server closes the connection.
+    pub limit: Option<usize>,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            mode: default_websocket_mode(),
+            interval: default_websocket_interval(),
+            size: default_websocket_size(),
+            limit: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(config: serde_json::Value) -> Result<(), RouteTableError> {
+        serde_json::from_value::<AppConfig>(config)
+            .unwrap()
+            .validate_routes()
+    }
+
+    #[test]
+    fn accepts_routes_merged_on_one_path() {
+        let routes = serde_json::json!({"routes": {
+            "list": {"path": "/items", "method": "GET"},
+            "create": {"path": "/items", "method": "POST"},
+            "item": {"path": "/items/{id}", "method": "GET"},
+            "archive": {"path": "/items/{id}", "method": "DELETE"},
+        }});
+
+        assert!(validate(routes).is_ok());
+    }
+
+    #[test]
+    fn rejects_the_same_method_on_one_path() {
+        let routes = serde_json::json!({"routes": {
+            "a": {"path": "/items", "method": "GET"},
+            "b": {"path": "/items"},
+        }});
+
+        assert!(matches!(
+            validate(routes),
+            Err(RouteTableError::DuplicateMethod(a, b, Method::Get, path))
+                if a == "a" && b == "b" && path == "/items"
+        ));
+    }
+
+    #[test]
+    fn rejects_routes_overlapping_a_resource() {
+        let routes = serde_json::json!({"routes": {
+            "items": {"path": "/items/", "kind": "RESOURCE"},
+            "update": {"path": "/items/{id}", "method": "PUT"},
+        }});
+
+        assert!(matches!(
+            validate(routes),
+            Err(RouteTableError::DuplicateMethod(_, _, Method::Put, _))
+        ));
+    }
+
+    #[test]
+    fn rejects_routes_overlapping_built_in_endpoints() {
+        let routes = serde_json::json!({
+            "journal": {"enabled": true, "path": "/journal"},
+            "routes": {"clear": {"path": "/journal", "method": "DELETE"}},
+        });
+
+        assert!(matches!(
+            validate(routes),
+            Err(RouteTableError::DuplicateMethod(journal, _, Method::Delete, _))
+                if journal == "journal"
+        ));
+    }
+
+    #[test]
+    fn rejects_differently_named_parameters_on_one_path() {
+        let routes = serde_json::json!({"routes": {
+            "get": {"path": "/items/{id}", "method": "GET"},
+            "delete": {"path": "/items/{name}", "method": "DELETE"},
+        }});
+
+        assert!(matches!(
+            validate(routes),
+            Err(RouteTableError::ConflictingParameters(..))
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_tables_and_relative_paths() {
+        assert!(matches!(
+            validate(serde_json::json!({})),
+            Err(RouteTableError::Empty)
+        ));
+        assert!(matches!(
+            validate(serde_json::json!({"routes": {"a": {"path": "items"}}})),
+            Err(RouteTableError::InvalidPath(..))
+        ));
+    }
+}
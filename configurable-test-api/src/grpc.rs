@@ -0,0 +1,137 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::Request,
+    routing::{MethodRouter, post},
+};
+use futures::{StreamExt, stream};
+use shared::grpc::{EchoRequest, EchoResponse, SERVER_STREAM_PATH, UNARY_PATH};
+use tokio::time::sleep;
+use tonic::{
+    Code, Status,
+    codegen::{BoxFuture, BoxStream},
+    server::{Grpc, ServerStreamingService, UnaryService},
+};
+use tonic_prost::ProstCodec;
+
+use crate::config::GrpcConfig;
+
+/// Behaviour shared by every method of the echo service.
+struct Echo {
+    latency: Duration,
+    code: Code,
+    message: String,
+    count: u32,
+    delay: Duration,
+    instance: String,
+}
+
+impl Echo {
+    /// Applies the configured latency, then fails the call unless the configured code is OK.
+    async fn respond(&self) -> Result<(), Status> {
+        sleep(self.latency).await;
+
+        match self.code {
+            Code::Ok => Ok(()),
+            code => Err(Status::new(code, self.message.as_str())),
+        }
+    }
+}
+
+struct Unary(Arc<Echo>);
+
+impl UnaryService<EchoRequest> for Unary {
+    type Response = EchoResponse;
+    type Future = BoxFuture<tonic::Response<Self::Response>, Status>;
+
+    fn call(&mut self, request: tonic::Request<EchoRequest>) -> Self::Future {
+        let echo = self.0.clone();
+
+        Box::pin(async move {
+            echo.respond().await?;
+
+            Ok(tonic::Response::new(EchoResponse {
+                message: request.into_inner().message,
+                instance: echo.instance.clone(),
+            }))
+        })
+    }
+}
+
+struct ServerStream(Arc<Echo>);
+
+impl ServerStreamingService<EchoRequest> for ServerStream {
+    type Response = EchoResponse;
+    type ResponseStream = BoxStream<EchoResponse>;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: tonic::Request<EchoRequest>) -> Self::Future {
+        let echo = self.0.clone();
+
+        Box::pin(async move {
+            echo.respond().await?;
+
+            let EchoRequest { message, count } = request.into_inner();
+            let count = if count == 0 { echo.count } else { count };
+
+            let responses = stream::iter(0..count).then(move |idx| {
+                let echo = echo.clone();
+                let message = message.clone();
+
+                async move {
+                    if idx > 0 {
+                        sleep(echo.delay).await;
+                    }
+
+                    Ok(EchoResponse {
+                        message,
+                        instance: echo.instance.clone(),
+                    })
+                }
+            });
+
+            Ok(tonic::Response::new(
+                Box::pin(responses) as Self::ResponseStream
+            ))
+        })
+    }
+}
+
+/// Builds the `testapi.Echo` service routes, see [`shared::grpc`] for the message definitions.
+pub(crate) fn make_grpc_routes<S>(
+    config: &GrpcConfig,
+    instance: &str,
+) -> [(String, MethodRouter<S>); 2]
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let echo = Arc::new(Echo {
+        latency: Duration::from_millis(config.latency),
+        code: Code::from(config.code),
+        message: config.message.clone(),
+        count: config.count,
+        delay: Duration::from_millis(config.delay),
+        instance: instance.to_owned(),
+    });
+
+    let unary = {
+        let echo = echo.clone();
+
+        post(async move |request: Request| {
+            Grpc::new(ProstCodec::default())
+                .unary(Unary(echo.clone()), request)
+                .await
+        })
+    };
+
+    let server_stream = post(async move |request: Request| {
+        Grpc::new(ProstCodec::default())
+            .server_streaming(ServerStream(echo.clone()), request)
+            .await
+    });
+
+    [
+        (UNARY_PATH.to_owned(), unary),
+        (SERVER_STREAM_PATH.to_owned(), server_stream),
+    ]
+}
@@ -0,0 +1,313 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{StatusCode, status::InvalidStatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{MethodRouter, get},
+};
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::{
+    config::{DegradedConfig, HealthConfig, HealthTriggerConfig},
+    shutdown::Shutdown,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum HealthState {
+    Starting,
+    Healthy,
+    Degraded,
+    Unhealthy,
+    Draining,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct HealthResponse {
+    pub state: HealthState,
+    pub uptime: u64,
+    pub requests: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Trigger {
+    requests: Option<u64>,
+    after: Option<Duration>,
+}
+
+impl Trigger {
+    fn fired(&self, requests: u64, uptime: Duration) -> bool {
+        self.requests.is_some_and(|r| requests >= r) || self.after.is_some_and(|a| uptime >= a)
+    }
+}
+
+impl From<&HealthTriggerConfig> for Trigger {
+    fn from(value: &HealthTriggerConfig) -> Self {
+        Self {
+            requests: value.requests,
+            after: value.after.map(Duration::from_millis),
+        }
+    }
+}
+
+impl From<&DegradedConfig> for Trigger {
+    fn from(value: &DegradedConfig) -> Self {
+        Self {
+            requests: value.requests,
+            after: value.after.map(Duration::from_millis),
+        }
+    }
+}
+
+/// Lifecycle of this instance as reported by the liveness and readiness endpoints. The state is
+/// derived on each probe from the uptime, the number of requests served and the shutdown
+/// progress, so it is the same for every probe at a given point in time.
+#[derive(Debug)]
+pub(crate) struct Health {
+    started: Instant,
+    requests: AtomicU64,
+    start: Duration,
+    unhealthy: Trigger,
+    degraded: Trigger,
+    degraded_status: StatusCode,
+    degraded_body: Option<String>,
+    flap: Option<(Duration, Duration)>,
+    shutdown: Arc<Shutdown>,
+}
+
+impl Health {
+    pub(crate) fn new(
+        config: &HealthConfig,
+        shutdown: Arc<Shutdown>,
+    ) -> Result<Arc<Self>, InvalidStatusCode> {
+        Ok(Arc::new(Self {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            start: Duration::from_millis(config.start),
+            unhealthy: (&config.unhealthy).into(),
+            degraded: (&config.degraded).into(),
+            degraded_status: StatusCode::from_u16(config.degraded.status)?,
+            degraded_body: config.degraded.body.clone(),
+            flap: (config.flap.down > 0).then(|| {
+                (
+                    Duration::from_millis(config.flap.up),
+                    Duration::from_millis(config.flap.down),
+                )
+            }),
+            shutdown,
+        }))
+    }
+
+    pub(crate) fn state(&self) -> HealthState {
+        let uptime = self.started.elapsed();
+        let requests = self.requests.load(Ordering::Relaxed);
+
+        if self.shutdown.is_lame_duck() {
+            return HealthState::Draining;
+        }
+
+        if uptime < self.start {
+            return HealthState::Starting;
+        }
+
+        if self.unhealthy.fired(requests, uptime) {
+            return HealthState::Unhealthy;
+        }
+
+        if let Some((up, down)) = self.flap {
+            let phase = (uptime - self.start).as_millis() % (up + down).as_millis();
+
+            if phase >= up.as_millis() {
+                return HealthState::Unhealthy;
+            }
+        }
+
+        if self.degraded.fired(requests, uptime) {
+            return HealthState::Degraded;
+        }
+
+        HealthState::Healthy
+    }
+
+    fn respond(&self, ready: bool) -> Response {
+        let state = self.state();
+
+        let status = match state {
+            HealthState::Healthy => StatusCode::OK,
+            HealthState::Degraded => {
+                if let Some(body) = &self.degraded_body {
+                    return (self.degraded_status, body.clone()).into_response();
+                }
+
+                self.degraded_status
+            }
+            HealthState::Starting | HealthState::Draining if !ready => StatusCode::OK,
+            HealthState::Starting | HealthState::Draining | HealthState::Unhealthy => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        };
+
+        (
+            status,
+            Json(HealthResponse {
+                state,
+                uptime: self.started.elapsed().as_millis() as u64,
+                requests: self.requests.load(Ordering::Relaxed),
+            }),
+        )
+            .into_response()
+    }
+
+    /// Liveness fails only while unhealthy, readiness also fails while starting and draining.
+    pub(crate) fn make_routes<S>(
+        self: &Arc<Self>,
+        live: &str,
+        ready: &str,
+    ) -> [(String, MethodRouter<S>); 2]
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let live_health = self.clone();
+        let ready_health = self.clone();
+
+        [
+            (
+                live.to_owned(),
+                get(async move || live_health.respond(false)),
+            ),
+            (
+                ready.to_owned(),
+                get(async move || ready_health.respond(true)),
+            ),
+        ]
+    }
+}
+
+/// Middleware counting the requests served by the routes towards the health triggers.
+pub(crate) async fn count_request(
+    State(health): State<Arc<Health>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    health.requests.fetch_add(1, Ordering::Relaxed);
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::time::advance;
+
+    use super::*;
+
+    fn health(config: serde_json::Value) -> (Arc<Health>, Arc<Shutdown>) {
+        let shutdown = Shutdown::new();
+        let health =
+            Health::new(&serde_json::from_value(config).unwrap(), shutdown.clone()).unwrap();
+
+        (health, shutdown)
+    }
+
+    fn serve(health: &Health, requests: u64) {
+        health.requests.fetch_add(requests, Ordering::Relaxed);
+    }
+
+    fn statuses(health: &Health) -> (StatusCode, StatusCode) {
+        (
+            health.respond(false).status(),
+            health.respond(true).status(),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn becomes_ready_after_the_slow_start() {
+        let (health, _) = health(json!({ "start": 100 }));
+
+        assert_eq!(health.state(), HealthState::Starting);
+        assert_eq!(
+            statuses(&health),
+            (StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE)
+        );
+
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(health.state(), HealthState::Healthy);
+        assert_eq!(statuses(&health), (StatusCode::OK, StatusCode::OK));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn degrades_then_fails_on_the_triggers() {
+        let (health, _) = health(json!({
+            "unhealthy": { "after": 1000 },
+            "degraded": { "requests": 2, "status": 299 },
+        }));
+
+        serve(&health, 1);
+        assert_eq!(health.state(), HealthState::Healthy);
+
+        serve(&health, 1);
+        assert_eq!(health.state(), HealthState::Degraded);
+        assert_eq!(
+            statuses(&health),
+            (
+                StatusCode::from_u16(299).unwrap(),
+                StatusCode::from_u16(299).unwrap()
+            )
+        );
+
+        advance(Duration::from_millis(1000)).await;
+        assert_eq!(health.state(), HealthState::Unhealthy);
+        assert_eq!(
+            statuses(&health),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::SERVICE_UNAVAILABLE
+            )
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flaps_once_started() {
+        let (health, _) = health(json!({ "start": 50, "flap": { "up": 100, "down": 20 } }));
+
+        advance(Duration::from_millis(50)).await;
+        assert_eq!(health.state(), HealthState::Healthy);
+
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(health.state(), HealthState::Unhealthy);
+
+        advance(Duration::from_millis(20)).await;
+        assert_eq!(health.state(), HealthState::Healthy);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_takes_precedence_over_every_state() {
+        let (health, shutdown) = health(json!({ "start": 100, "unhealthy": { "requests": 1 } }));
+
+        shutdown.enter_lame_duck();
+        assert_eq!(health.state(), HealthState::Draining);
+        assert_eq!(
+            statuses(&health),
+            (StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE)
+        );
+
+        advance(Duration::from_millis(100)).await;
+        serve(&health, 1);
+        shutdown.drain();
+        assert_eq!(health.state(), HealthState::Draining);
+        assert_eq!(
+            statuses(&health),
+            (StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE)
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use axum::{
+    extract::{Request, State},
+    http::{
+        HeaderName, HeaderValue, Version, header::InvalidHeaderName, header::InvalidHeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+};
+
+use crate::config::InstanceConfig;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum InstanceError {
+    #[error("Invalid instance header name: {0}")]
+    InvalidHeaderName(#[from] InvalidHeaderName),
+    #[error("Invalid instance id: {0}")]
+    InvalidId(#[from] InvalidHeaderValue),
+}
+
+/// Identity of this test API instance, attached to every response so clients can tell which
+/// instance behind a load balancer answered.
+#[derive(Debug, Clone)]
+pub(crate) struct Instance {
+    pub id: String,
+    pub header_name: HeaderName,
+    pub header_value: HeaderValue,
+}
+
+impl TryFrom<&InstanceConfig> for Instance {
+    type Error = InstanceError;
+
+    fn try_from(value: &InstanceConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: value.id.clone(),
+            header_name: HeaderName::try_from(value.header.as_str())?,
+            header_value: HeaderValue::try_from(value.id.as_str())?,
+        })
+    }
+}
+
+/// Header reporting the HTTP version negotiated for the request.
+pub(crate) const PROTOCOL_HEADER: &str = "x-protocol";
+
+/// Tags every response with the instance id and the negotiated protocol.
+pub(crate) async fn tag_response(
+    State(instance): State<Instance>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let version = request.version();
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    headers.insert(instance.header_name, instance.header_value);
+    headers.insert(
+        PROTOCOL_HEADER,
+        HeaderValue::from_static(protocol_name(version)),
+    );
+
+    response
+}
+
+pub(crate) fn protocol_name(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_11 => "HTTP/1.1",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "unknown",
+    }
+}
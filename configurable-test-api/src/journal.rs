@@ -0,0 +1,183 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Json,
+    extract::{Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+    routing::{MethodRouter, get},
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::instance::protocol_name;
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct JournalEntry {
+    pub route: String,
+    pub method: String,
+    pub version: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: BTreeMap<String, String>,
+    pub timestamp: u64,
+    pub duration: u64,
+    pub status: u16,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct JournalFilter {
+    pub route: Option<String>,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub status: Option<u16>,
+    /// Header filter in the form `name:value`, header names are matched case insensitively.
+    pub header: Option<String>,
+}
+
+impl JournalFilter {
+    fn matches(&self, entry: &JournalEntry) -> bool {
+        self.route.as_ref().is_none_or(|r| *r == entry.route)
+            && self
+                .method
+                .as_ref()
+                .is_none_or(|m| m.eq_ignore_ascii_case(&entry.method))
+            && self.path.as_ref().is_none_or(|p| *p == entry.path)
+            && self.status.is_none_or(|s| s == entry.status)
+            && self.header.as_ref().is_none_or(|h| {
+                let (name, value) = h.split_once(":").unwrap_or((h, ""));
+
+                entry
+                    .headers
+                    .get(&name.trim().to_ascii_lowercase())
+                    .is_some_and(|v| value.is_empty() || v == value.trim())
+            })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct JournalResponse {
+    pub count: usize,
+    pub requests: Vec<JournalEntry>,
+}
+
+/// Bounded in-memory record of the requests served by the configured routes. Once the capacity
+/// is reached the oldest entries are discarded.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    entries: RwLock<VecDeque<JournalEntry>>,
+    capacity: usize,
+}
+
+impl Journal {
+    pub(crate) fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            entries: Default::default(),
+            capacity,
+        })
+    }
+
+    pub(crate) fn record(&self, entry: JournalEntry) {
+        let mut guard = self.entries.write().expect("Failed to aquire write lock");
+
+        if guard.len() >= self.capacity {
+            guard.pop_front();
+        }
+
+        guard.push_back(entry);
+    }
+
+    pub(crate) fn find(&self, filter: &JournalFilter) -> Vec<JournalEntry> {
+        self.entries
+            .read()
+            .expect("Failed to aquire read lock")
+            .iter()
+            .filter(|e| filter.matches(e))
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn reset(&self) {
+        self.entries
+            .write()
+            .expect("Failed to aquire write lock")
+            .clear();
+    }
+
+    /// Router for the journal endpoint, `GET` lists recorded requests matching the query filter and
+    /// `DELETE` clears the journal.
+    pub(crate) fn make_router<S>(self: &Arc<Self>) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let list_journal = self.clone();
+        let reset_journal = self.clone();
+
+        get(async move |Query(filter): Query<JournalFilter>| {
+            let requests = list_journal.find(&filter);
+
+            Json(JournalResponse {
+                count: requests.len(),
+                requests,
+            })
+        })
+        .delete(async move || {
+            reset_journal.reset();
+            StatusCode::NO_CONTENT
+        })
+    }
+}
+
+/// Middleware recording each request handled by a route into the journal.
+pub(crate) async fn record_request(
+    State((journal, route)): State<(Arc<Journal>, String)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start_time = Instant::now();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+
+    let method = request.method().to_string();
+    let version = protocol_name(request.version()).to_owned();
+    let path = request.uri().path().to_owned();
+    let query = request.uri().query().map(str::to_owned);
+    let headers =
+        request
+            .headers()
+            .iter()
+            .fold(BTreeMap::<String, String>::new(), |mut acc, (k, v)| {
+                let value = String::from_utf8_lossy(v.as_bytes());
+
+                acc.entry(k.as_str().to_owned())
+                    .and_modify(|e| {
+                        e.push_str(", ");
+                        e.push_str(&value);
+                    })
+                    .or_insert_with(|| value.into_owned());
+                acc
+            });
+
+    let response = next.run(request).await;
+
+    journal.record(JournalEntry {
+        route,
+        method,
+        version,
+        path,
+        query,
+        headers,
+        timestamp,
+        duration: start_time.elapsed().as_millis() as u64,
+        status: response.status().as_u16(),
+    });
+
+    response
+}
@@ -0,0 +1,250 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::{Instant, timeout},
+};
+
+use crate::config::LimitConfig;
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    rate: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    /// Takes a token, or returns how long until the next one is available.
+    fn take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+
+        self.tokens =
+            (self.tokens + (now - self.refilled).as_secs_f64() * self.rate).min(self.capacity);
+        self.refilled = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+
+    /// Returns a token taken for a request which was rejected afterwards.
+    fn refund(&mut self) {
+        self.tokens = (self.tokens + 1.0).min(self.capacity);
+    }
+}
+
+/// Rate and concurrency limits shared by every request passing through one
+/// [`limit_request`] layer.
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    bucket: Option<Mutex<TokenBucket>>,
+    slots: Option<Semaphore>,
+    queued: AtomicUsize,
+    queue: usize,
+    timeout: Duration,
+}
+
+impl Limiter {
+    pub(crate) fn new(config: &LimitConfig) -> Arc<Self> {
+        Arc::new(Self {
+            bucket: config.rate.map(|rate| {
+                let capacity = config.burst.unwrap_or(rate).get() as f64;
+
+                Mutex::new(TokenBucket {
+                    tokens: capacity,
+                    capacity,
+                    rate: rate.get() as f64,
+                    refilled: Instant::now(),
+                })
+            }),
+            slots: config.concurrency.map(Semaphore::new),
+            queued: AtomicUsize::new(0),
+            queue: config.queue,
+            timeout: Duration::from_millis(config.timeout),
+        })
+    }
+
+    /// Waits for a free slot if there is room in the queue. Returns `None` when the queue is full
+    /// or the wait timed out.
+    async fn acquire<'a>(&self, slots: &'a Semaphore) -> Option<SemaphorePermit<'a>> {
+        if let Ok(permit) = slots.try_acquire() {
+            return Some(permit);
+        }
+
+        self.queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+                (queued < self.queue).then_some(queued + 1)
+            })
+            .ok()?;
+
+        let permit = timeout(self.timeout, slots.acquire())
+            .await
+            .ok()
+            .and_then(Result::ok);
+
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+
+        permit
+    }
+}
+
+/// Middleware applying a [`Limiter`]. The concurrency slot is held until the response head is
+/// produced, streamed bodies do not count against the limit.
+pub(crate) async fn limit_request(
+    State(limiter): State<Arc<Limiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(bucket) = &limiter.bucket {
+        let taken = bucket.lock().expect("Failed to aquire lock").take();
+
+        if let Err(wait) = taken {
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.to_string())],
+            )
+                .into_response();
+        }
+    }
+
+    let _permit = match &limiter.slots {
+        Some(slots) => match limiter.acquire(slots).await {
+            Some(permit) => Some(permit),
+            None => {
+                if let Some(bucket) = &limiter.bucket {
+                    bucket.lock().expect("Failed to aquire lock").refund();
+                }
+
+                return StatusCode::SERVICE_UNAVAILABLE.into_response();
+            }
+        },
+        None => None,
+    };
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use tokio::time::advance;
+
+    use super::*;
+
+    fn limiter(configure: impl FnOnce(&mut LimitConfig)) -> Arc<Limiter> {
+        let mut config = LimitConfig::default();
+        configure(&mut config);
+
+        Limiter::new(&config)
+    }
+
+    fn take(limiter: &Limiter) -> Result<(), Duration> {
+        limiter.bucket.as_ref().unwrap().lock().unwrap().take()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn admits_a_burst_then_rejects_until_refilled() {
+        let limiter = limiter(|c| {
+            c.rate = NonZeroU32::new(2);
+            c.burst = NonZeroU32::new(3);
+        });
+
+        assert!((0..3).all(|_| take(&limiter).is_ok()));
+        assert_eq!(take(&limiter), Err(Duration::from_millis(500)));
+
+        advance(Duration::from_millis(250)).await;
+        assert_eq!(take(&limiter), Err(Duration::from_millis(250)));
+
+        advance(Duration::from_millis(250)).await;
+        assert!(take(&limiter).is_ok());
+        assert!(take(&limiter).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refills_up_to_the_burst() {
+        let limiter = limiter(|c| c.rate = NonZeroU32::new(2));
+
+        assert!((0..2).all(|_| take(&limiter).is_ok()));
+
+        advance(Duration::from_secs(10)).await;
+        assert!((0..2).all(|_| take(&limiter).is_ok()));
+        assert!(take(&limiter).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refund_returns_a_token_without_exceeding_the_burst() {
+        let limiter = limiter(|c| c.rate = NonZeroU32::new(1));
+        let bucket = limiter.bucket.as_ref().unwrap();
+
+        bucket.lock().unwrap().refund();
+        assert!(take(&limiter).is_ok());
+        assert!(take(&limiter).is_err());
+
+        bucket.lock().unwrap().refund();
+        assert!(take(&limiter).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejects_beyond_concurrency_without_a_queue() {
+        let limiter = limiter(|c| c.concurrency = Some(1));
+        let slots = limiter.slots.as_ref().unwrap();
+
+        let permit = limiter.acquire(slots).await;
+        assert!(permit.is_some());
+        assert!(limiter.acquire(slots).await.is_none());
+
+        drop(permit);
+        assert!(limiter.acquire(slots).await.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queued_requests_wait_for_a_slot_until_the_timeout() {
+        let limiter = limiter(|c| {
+            c.concurrency = Some(1);
+            c.queue = 1;
+            c.timeout = 100;
+        });
+        let slots = limiter.slots.as_ref().unwrap();
+
+        let permit = limiter.acquire(slots).await;
+        let started = Instant::now();
+        assert!(limiter.acquire(slots).await.is_none());
+        assert_eq!(started.elapsed(), Duration::from_millis(100));
+
+        let (queued, overflow, ()) = tokio::join!(
+            limiter.acquire(slots),
+            async {
+                tokio::task::yield_now().await;
+                limiter.acquire(slots).await
+            },
+            async {
+                advance(Duration::from_millis(50)).await;
+                drop(permit);
+            }
+        );
+
+        assert!(queued.is_some());
+        assert!(overflow.is_none());
+    }
+}
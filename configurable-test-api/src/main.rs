@@ -1,74 +1,346 @@
-use axum::Router;
-use figment::{Figment, providers::Env};
+use std::{collections::BTreeMap, path::PathBuf, process::ExitCode, time::Duration};
+
+use axum::{Router, middleware, routing::MethodRouter};
+use clap::Parser;
 use log::info;
-use tokio::signal::unix::{SignalKind, signal};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    time::{sleep, timeout},
+};
 
 use crate::{
     callback::make_callback,
-    config::{AppConfig, RouteConfig},
+    config::{AppConfig, Protocol, RouteKind},
+    grpc::make_grpc_routes,
+    health::{Health, count_request},
+    instance::{Instance, tag_response},
+    journal::{Journal, record_request},
+    limit::{Limiter, limit_request},
+    resource::make_resource_routes,
+    server::serve,
+    shutdown::{Shutdown, fail_health},
+    stream::{make_download_callback, make_events_callback, make_stream_callback},
+    tls::make_acceptor,
+    websocket::make_websocket_callback,
+    work::{Work, simulate_work},
 };
 
 mod callback;
 mod config;
+mod grpc;
+mod health;
+mod instance;
+mod journal;
+mod limit;
+mod resource;
+mod server;
+mod shutdown;
+mod stream;
+mod tls;
+mod websocket;
+mod work;
+
+/// HTTP, WebSocket and gRPC test API configured through a config file and `APP_` environment
+/// variables.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// YAML, TOML or JSON config file, `APP_` environment variables override its values.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Print the effective configuration as JSON and exit.
+    #[arg(long)]
+    print_config: bool,
+    /// Build every route, template, matcher and certificate without serving, then exit.
+    #[arg(long)]
+    validate: bool,
+}
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     env_logger::init();
 
-    let AppConfig { port, routes } = match Figment::new()
-        .merge(Env::prefixed("APP_").split("_"))
-        .extract()
-    {
+    let args = Args::parse();
+
+    let app_config = match AppConfig::load(args.config.as_deref()) {
         Ok(cfg) => cfg,
         Err(e) => {
             log::error!("Error while parsing config: {e}");
 
-            return;
+            return ExitCode::FAILURE;
         }
     };
 
-    let listener = match tokio::net::TcpListener::bind(&format!("0.0.0.0:{}", port))
-        .await
-        .map_err(Box::new)
-    {
-        Ok(listener) => listener,
+    if let Err(e) = app_config.validate_routes() {
+        log::error!("Error while validating routes: {e}");
+
+        return ExitCode::FAILURE;
+    }
+
+    if args.print_config {
+        match serde_json::to_string_pretty(&app_config) {
+            Ok(config) => println!("{config}"),
+            Err(e) => {
+                log::error!("Error while printing config: {e}");
+
+                return ExitCode::FAILURE;
+            }
+        }
+
+        return ExitCode::SUCCESS;
+    }
+
+    let AppConfig {
+        port,
+        routes,
+        journal: journal_config,
+        instance: instance_config,
+        tls: tls_config,
+        protocol,
+        grpc: grpc_config,
+        limit: limit_config,
+        shutdown: shutdown_config,
+        health: health_config,
+    } = app_config;
+
+    let shutdown = Shutdown::new();
+
+    info!("Using protocol: {protocol}");
+    info!("Using shutdown: {shutdown_config}");
+
+    info!("Using instance: {instance_config}");
+
+    let instance = match Instance::try_from(&instance_config).map_err(Box::new) {
+        Ok(instance) => instance,
         Err(e) => {
-            log::error!("Error while creating listener: {e}");
-            return;
+            log::error!("Error while parsing instance config: {e}");
+            return ExitCode::FAILURE;
         }
     };
 
-    let mut app = Router::new();
+    let acceptor = if tls_config.enabled {
+        info!("Using TLS: {tls_config}");
 
-    for (_, route) in routes.into_iter() {
-        info!("Using route: {route}");
-        let RouteConfig {
-            path,
-            method,
-            latency,
-        } = route;
-
-        let callback = match make_callback(&method, latency).map_err(Box::new) {
-            Ok(callback) => callback,
+        match make_acceptor(
+            &tls_config,
+            vec!["localhost".to_owned(), instance.id.clone()],
+            protocol,
+        )
+        .map_err(Box::new)
+        {
+            Ok(acceptor) => Some(acceptor),
             Err(e) => {
-                log::error!("Error while building route callback: {e}");
-                return;
+                log::error!("Error while configuring TLS: {e}");
+                return ExitCode::FAILURE;
             }
+        }
+    } else {
+        None
+    };
+
+    let default_body = if instance_config.body {
+        "hello from {{instance}}"
+    } else {
+        "hello"
+    };
+
+    let journal = if journal_config.enabled {
+        info!("Using request journal: {journal_config}");
+
+        Some(Journal::new(journal_config.capacity))
+    } else {
+        None
+    };
+
+    let mut route_callbacks = Vec::new();
+
+    for (name, route) in routes.into_iter() {
+        info!("Using route: {route}");
+
+        let callbacks = match route.kind {
+            RouteKind::Static => {
+                match make_callback(&route, default_body, &instance.id).map_err(Box::new) {
+                    Ok(callback) => vec![(route.path.clone(), callback)],
+                    Err(e) => {
+                        log::error!("Error while building route callback: {e}");
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            RouteKind::Resource => make_resource_routes(&route).into(),
+            RouteKind::Stream => vec![(route.path.clone(), make_stream_callback(&route))],
+            RouteKind::Events => vec![(route.path.clone(), make_events_callback(&route))],
+            RouteKind::Download => vec![(route.path.clone(), make_download_callback(&route))],
+            RouteKind::WebSocket => vec![(route.path.clone(), make_websocket_callback(&route))],
         };
 
-        app = app.route(&path, callback);
+        let limiter = route.limit.is_enabled().then(|| {
+            info!("Using limit for route: {name}, {}", route.limit);
+
+            Limiter::new(&route.limit)
+        });
+
+        let health = (shutdown_config.health.as_ref() == Some(&name)).then(|| {
+            info!("Using route: {name} as health route during shutdown");
+
+            shutdown.clone()
+        });
+
+        let work = route.work.is_enabled().then(|| {
+            info!("Using work for route: {name}, {}", route.work);
+
+            Work::new(&route.work)
+        });
+
+        route_callbacks.push((name, health, work, limiter, callbacks));
+    }
+
+    if grpc_config.enabled {
+        info!("Using gRPC service: {grpc_config}");
+
+        if protocol == Protocol::Http1 {
+            log::warn!("gRPC requires HTTP/2, but the listener only accepts HTTP/1");
+        }
+
+        route_callbacks.push((
+            "grpc".to_owned(),
+            None,
+            None,
+            None,
+            make_grpc_routes(&grpc_config, &instance.id).into(),
+        ));
     }
 
+    let mut method_routers: BTreeMap<String, MethodRouter> = BTreeMap::new();
+
+    for (name, health, work, limiter, callbacks) in route_callbacks {
+        for (path, callback) in callbacks {
+            let callback = match &work {
+                Some(work) => {
+                    callback.layer(middleware::from_fn_with_state(work.clone(), simulate_work))
+                }
+                None => callback,
+            };
+
+            let callback = match &limiter {
+                Some(limiter) => callback.layer(middleware::from_fn_with_state(
+                    limiter.clone(),
+                    limit_request,
+                )),
+                None => callback,
+            };
+
+            let callback = match &health {
+                Some(shutdown) => callback.layer(middleware::from_fn_with_state(
+                    shutdown.clone(),
+                    fail_health,
+                )),
+                None => callback,
+            };
+
+            let callback = match &journal {
+                Some(journal) => callback.layer(middleware::from_fn_with_state(
+                    (journal.clone(), name.clone()),
+                    record_request,
+                )),
+                None => callback,
+            };
+
+            // Routes sharing a path are merged, overlapping methods were rejected by validation.
+            let callback = match method_routers.remove(&path) {
+                Some(existing) => existing.merge(callback),
+                None => callback,
+            };
+
+            method_routers.insert(path, callback);
+        }
+    }
+
+    let mut app = method_routers
+        .into_iter()
+        .fold(Router::new(), |app, (path, callback)| {
+            app.route(&path, callback)
+        });
+
+    let health = if health_config.enabled {
+        info!("Using health: {health_config}");
+
+        match Health::new(&health_config, shutdown.clone()) {
+            Ok(health) => Some(health),
+            Err(e) => {
+                log::error!("Error while building health routes: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some(health) = &health {
+        app = app.layer(middleware::from_fn_with_state(
+            health.clone(),
+            count_request,
+        ));
+    }
+
+    if limit_config.is_enabled() {
+        info!("Using instance limit: {limit_config}");
+
+        app = app.layer(middleware::from_fn_with_state(
+            Limiter::new(&limit_config),
+            limit_request,
+        ));
+    }
+
+    if let Some(journal) = &journal {
+        app = app.route(&journal_config.path, journal.make_router());
+    }
+
+    if let Some(health) = &health {
+        for (path, callback) in health.make_routes(&health_config.live, &health_config.ready) {
+            let callback = match &journal {
+                Some(journal) => callback.layer(middleware::from_fn_with_state(
+                    (journal.clone(), "health".to_owned()),
+                    record_request,
+                )),
+                None => callback,
+            };
+
+            app = app.route(&path, callback);
+        }
+    }
+
+    let app = app.layer(middleware::from_fn_with_state(instance, tag_response));
+
     let mut sigint = signal(SignalKind::interrupt()).expect("Failed to get interrupt signal");
     let mut sigterm = signal(SignalKind::terminate()).expect("Failed to get terminate signal");
 
+    if args.validate {
+        println!("Configuration is valid");
+
+        return ExitCode::SUCCESS;
+    }
+
+    let listener = match tokio::net::TcpListener::bind(&format!("0.0.0.0:{}", port))
+        .await
+        .map_err(Box::new)
+    {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Error while creating listener: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let server = tokio::spawn(serve(
+        listener,
+        acceptor,
+        app,
+        protocol,
+        shutdown.subscribe(),
+    ));
+
     tokio::select!(
-      r =  axum::serve(listener, app) => match r {
-          Ok(_) => (),
-          Err(e) => {
-              log::error!("Error while parsing config: {e}");
-          }
-      },
       _ = sigint.recv() => {
         log::info!("Recieved SIGINT, shutting down...")
       },
@@ -76,4 +348,20 @@ async fn main() {
         log::info!("Recieved SIGTERM, shutting down...")
       },
     );
+
+    if shutdown_config.lame > 0 {
+        info!("Entering lame duck for {}ms", shutdown_config.lame);
+
+        shutdown.enter_lame_duck();
+        sleep(Duration::from_millis(shutdown_config.lame)).await;
+    }
+
+    shutdown.drain();
+
+    match timeout(Duration::from_millis(shutdown_config.drain), server).await {
+        Ok(_) => info!("All connections drained"),
+        Err(_) => log::warn!("Drain timeout elapsed, closing remaining connections"),
+    }
+
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,230 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::Path,
+    http::{StatusCode, header::LOCATION},
+    response::{IntoResponse, Response},
+    routing::{MethodRouter, get},
+};
+use serde_json::{Map, Value};
+use tokio::time::sleep;
+
+use crate::config::RouteConfig;
+
+/// Concurrent in-memory store backing a resource route, items are keyed by a generated id.
+#[derive(Debug)]
+struct ResourceStore {
+    prefix: String,
+    items: RwLock<BTreeMap<u64, Value>>,
+    next_id: AtomicU64,
+    capacity: Option<usize>,
+    latency: Duration,
+    scale: Duration,
+}
+
+fn parse_body(body: &[u8]) -> Result<Value, (StatusCode, String)> {
+    serde_json::from_slice(body)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid JSON body: {e}")))
+}
+
+fn with_id(id: u64, value: Value) -> Value {
+    match value {
+        Value::Object(mut object) => {
+            object.insert("id".to_owned(), Value::from(id));
+            Value::Object(object)
+        }
+        other => other,
+    }
+}
+
+/// Applies a JSON merge patch (RFC 7386) to `target`.
+fn merge_patch(target: &mut Value, patch: Value) {
+    match patch {
+        Value::Object(patch) => {
+            if !target.is_object() {
+                *target = Value::Object(Map::new());
+            }
+
+            let object = target.as_object_mut().expect("Target is an object");
+
+            for (k, v) in patch {
+                if v.is_null() {
+                    object.remove(&k);
+                } else {
+                    merge_patch(object.entry(k).or_insert(Value::Null), v);
+                }
+            }
+        }
+        other => *target = other,
+    }
+}
+
+impl ResourceStore {
+    fn len(&self) -> usize {
+        self.items.read().expect("Failed to aquire read lock").len()
+    }
+
+    /// Latency grows with the number of stored items, emulating a backing store that slows down
+    /// as it fills.
+    async fn simulate_latency(&self) {
+        let items = u32::try_from(self.len()).unwrap_or(u32::MAX);
+
+        sleep(
+            self.latency
+                .saturating_add(self.scale.saturating_mul(items)),
+        )
+        .await;
+    }
+
+    async fn create(&self, body: Bytes) -> Response {
+        self.simulate_latency().await;
+
+        let value = match parse_body(&body) {
+            Ok(value) => value,
+            Err(rejection) => return rejection.into_response(),
+        };
+
+        let mut guard = self.items.write().expect("Failed to aquire write lock");
+
+        if self.capacity.is_some_and(|c| guard.len() >= c) {
+            return StatusCode::INSUFFICIENT_STORAGE.into_response();
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let value = with_id(id, value);
+
+        guard.insert(id, value.clone());
+
+        (
+            StatusCode::CREATED,
+            [(LOCATION, format!("{}/{}", self.prefix, id))],
+            Json(value),
+        )
+            .into_response()
+    }
+
+    async fn list(&self) -> Response {
+        self.simulate_latency().await;
+
+        let guard = self.items.read().expect("Failed to aquire read lock");
+
+        Json(guard.values().collect::<Vec<_>>()).into_response()
+    }
+
+    async fn get(&self, id: u64) -> Response {
+        self.simulate_latency().await;
+
+        match self
+            .items
+            .read()
+            .expect("Failed to aquire read lock")
+            .get(&id)
+        {
+            Some(value) => Json(value).into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn replace(&self, id: u64, body: Bytes) -> Response {
+        self.simulate_latency().await;
+
+        let value = match parse_body(&body) {
+            Ok(value) => with_id(id, value),
+            Err(rejection) => return rejection.into_response(),
+        };
+
+        match self
+            .items
+            .write()
+            .expect("Failed to aquire write lock")
+            .get_mut(&id)
+        {
+            Some(item) => {
+                *item = value.clone();
+                Json(value).into_response()
+            }
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn update(&self, id: u64, body: Bytes) -> Response {
+        self.simulate_latency().await;
+
+        let patch = match parse_body(&body) {
+            Ok(patch) => patch,
+            Err(rejection) => return rejection.into_response(),
+        };
+
+        match self
+            .items
+            .write()
+            .expect("Failed to aquire write lock")
+            .get_mut(&id)
+        {
+            Some(item) => {
+                merge_patch(item, patch);
+                *item = with_id(id, item.take());
+                Json(item.clone()).into_response()
+            }
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn delete(&self, id: u64) -> Response {
+        self.simulate_latency().await;
+
+        match self
+            .items
+            .write()
+            .expect("Failed to aquire write lock")
+            .remove(&id)
+        {
+            Some(_) => StatusCode::NO_CONTENT.into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+}
+
+/// Builds the collection (`/prefix`) and item (`/prefix/{id}`) routes for a resource route.
+pub fn make_resource_routes<S>(route: &RouteConfig) -> [(String, MethodRouter<S>); 2]
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let prefix = route.path.trim_end_matches("/").to_owned();
+
+    let store = Arc::new(ResourceStore {
+        prefix: prefix.clone(),
+        items: Default::default(),
+        next_id: AtomicU64::new(1),
+        capacity: route.resource.capacity,
+        latency: Duration::from_millis(route.latency),
+        scale: Duration::from_micros(route.resource.scale),
+    });
+
+    let (list_store, create_store) = (store.clone(), store.clone());
+
+    let collection = get(async move || list_store.list().await)
+        .post(async move |body: Bytes| create_store.create(body).await);
+
+    let (get_store, replace_store, update_store, delete_store) =
+        (store.clone(), store.clone(), store.clone(), store);
+
+    let item = get(async move |Path(id): Path<u64>| get_store.get(id).await)
+        .put(async move |Path(id): Path<u64>, body: Bytes| replace_store.replace(id, body).await)
+        .patch(async move |Path(id): Path<u64>, body: Bytes| update_store.update(id, body).await)
+        .delete(async move |Path(id): Path<u64>| delete_store.delete(id).await);
+
+    [
+        (prefix.clone(), collection),
+        (format!("{prefix}/{{id}}"), item),
+    ]
+}
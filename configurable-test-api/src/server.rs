@@ -0,0 +1,127 @@
+use std::pin::{Pin, pin};
+
+use axum::Router;
+use hyper::server::conn::{http1, http2};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    select,
+    sync::watch,
+    task::JoinSet,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{config::Protocol, shutdown::drained};
+
+/// Drives a connection to completion, asking it to close once idle when draining starts.
+async fn serve_until_drained<C, E>(
+    connection: C,
+    mut draining: watch::Receiver<bool>,
+    graceful_shutdown: impl FnOnce(Pin<&mut C>),
+) -> Result<(), E>
+where
+    C: Future<Output = Result<(), E>>,
+{
+    let mut connection = pin!(connection);
+
+    select! {
+        result = connection.as_mut() => return result,
+        () = drained(&mut draining) => graceful_shutdown(connection.as_mut()),
+    }
+
+    connection.await
+}
+
+async fn serve_connection<I>(
+    io: I,
+    app: Router,
+    protocol: Protocol,
+    draining: watch::Receiver<bool>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(io);
+    let service = TowerToHyperService::new(app);
+
+    let result = match protocol {
+        Protocol::Auto => {
+            let builder = auto::Builder::new(TokioExecutor::new());
+
+            serve_until_drained(
+                builder.serve_connection_with_upgrades(io, service),
+                draining,
+                |c| c.graceful_shutdown(),
+            )
+            .await
+        }
+        Protocol::Http1 => serve_until_drained(
+            http1::Builder::new()
+                .serve_connection(io, service)
+                .with_upgrades(),
+            draining,
+            |c| c.graceful_shutdown(),
+        )
+        .await
+        .map_err(Into::into),
+        Protocol::Http2 => serve_until_drained(
+            http2::Builder::new(TokioExecutor::new()).serve_connection(io, service),
+            draining,
+            |c| c.graceful_shutdown(),
+        )
+        .await
+        .map_err(Into::into),
+    };
+
+    if let Err(e) = result {
+        log::debug!("Error while serving connection: {e}");
+    }
+}
+
+/// Accepts connections until draining starts, optionally terminating TLS before handing each
+/// connection to the router. Returns once every open connection has closed.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    app: Router,
+    protocol: Protocol,
+    mut draining: watch::Receiver<bool>,
+) {
+    let mut connections = JoinSet::new();
+
+    loop {
+        let (stream, remote_addr) = select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::error!("Error while accepting connection: {e}");
+                    continue;
+                }
+            },
+            Some(_) = connections.join_next() => continue,
+            () = drained(&mut draining) => break,
+        };
+
+        let app = app.clone();
+        let acceptor = acceptor.clone();
+        let draining = draining.clone();
+
+        connections.spawn(async move {
+            match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => serve_connection(stream, app, protocol, draining).await,
+                    Err(e) => log::debug!("TLS handshake with {remote_addr} failed: {e}"),
+                },
+                None => serve_connection(stream, app, protocol, draining).await,
+            }
+        });
+    }
+
+    log::info!("Draining {} open connections", connections.len());
+
+    connections.join_all().await;
+}
@@ -0,0 +1,66 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::sync::watch;
+
+/// Shutdown progress shared between the signal handler, the server and the health route.
+#[derive(Debug)]
+pub(crate) struct Shutdown {
+    lame_duck: AtomicBool,
+    draining: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self {
+            lame_duck: AtomicBool::new(false),
+            draining: watch::Sender::new(false),
+        })
+    }
+
+    /// Fails the health route, the instance otherwise keeps serving.
+    pub(crate) fn enter_lame_duck(&self) {
+        self.lame_duck.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn is_lame_duck(&self) -> bool {
+        self.lame_duck.load(Ordering::Acquire)
+    }
+
+    /// Stops accepting connections and asks open connections to close once idle.
+    pub(crate) fn drain(&self) {
+        self.enter_lame_duck();
+        self.draining.send_replace(true);
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<bool> {
+        self.draining.subscribe()
+    }
+}
+
+/// Waits until [`Shutdown::drain`] is called.
+pub(crate) async fn drained(receiver: &mut watch::Receiver<bool>) {
+    // The sender is only dropped once the process exits, treat it as draining regardless.
+    let _ = receiver.wait_for(|draining| *draining).await;
+}
+
+/// Middleware failing the health route with `503` once shutdown has started.
+pub(crate) async fn fail_health(
+    State(shutdown): State<Arc<Shutdown>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if shutdown.is_lame_duck() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    next.run(request).await
+}
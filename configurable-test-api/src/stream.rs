@@ -0,0 +1,110 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    body::{Body, Bytes},
+    http::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE},
+    response::{
+        IntoResponse,
+        sse::{Event, Sse},
+    },
+    routing::{MethodRouter, on},
+};
+use futures::{StreamExt, stream};
+use tokio::time::sleep;
+
+use crate::{callback::method_filter, config::RouteConfig};
+
+const DOWNLOAD_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Streams `chunks` chunks of `size` bytes with `delay` between each, using chunked transfer
+/// encoding so clients observe each chunk as it is sent.
+pub(crate) fn make_stream_callback<S>(route: &RouteConfig) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let latency = Duration::from_millis(route.latency);
+    let delay = Duration::from_millis(route.stream.delay);
+    let chunks = route.stream.chunks;
+    let chunk = Bytes::from(vec![b'x'; route.stream.size]);
+
+    on(method_filter(route.method), async move || {
+        sleep(latency).await;
+
+        let body = stream::iter(0..chunks).then(move |idx| {
+            let chunk = chunk.clone();
+
+            async move {
+                if idx > 0 {
+                    sleep(delay).await;
+                }
+
+                Ok::<_, Infallible>(chunk)
+            }
+        });
+
+        Body::from_stream(body)
+    })
+}
+
+/// Sends `chunks` Server-Sent Events each carrying `size` bytes of data, `delay` apart.
+pub(crate) fn make_events_callback<S>(route: &RouteConfig) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let latency = Duration::from_millis(route.latency);
+    let delay = Duration::from_millis(route.stream.delay);
+    let chunks = route.stream.chunks;
+    let data = "x".repeat(route.stream.size);
+
+    on(method_filter(route.method), async move || {
+        sleep(latency).await;
+
+        let data = data.clone();
+        let events = stream::iter(0..chunks).then(move |idx| {
+            let event = Event::default().id(idx.to_string()).data(&data);
+
+            async move {
+                if idx > 0 {
+                    sleep(delay).await;
+                }
+
+                Ok::<_, Infallible>(event)
+            }
+        });
+
+        Sse::new(events)
+    })
+}
+
+/// Serves a file of `size` bytes with a known content length, written as fast as the client
+/// reads it.
+pub(crate) fn make_download_callback<S>(route: &RouteConfig) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let latency = Duration::from_millis(route.latency);
+    let size = route.download.size;
+    let chunk = Bytes::from(vec![b'x'; DOWNLOAD_CHUNK_SIZE as usize]);
+
+    on(method_filter(route.method), async move || {
+        sleep(latency).await;
+
+        let chunk = chunk.clone();
+        let body =
+            stream::iter((0..size).step_by(DOWNLOAD_CHUNK_SIZE as usize)).map(move |offset| {
+                Ok::<_, Infallible>(
+                    chunk.slice(0..(size - offset).min(DOWNLOAD_CHUNK_SIZE) as usize),
+                )
+            });
+
+        (
+            [
+                (CONTENT_TYPE, "application/octet-stream".to_owned()),
+                (CONTENT_LENGTH, size.to_string()),
+                (CONTENT_DISPOSITION, "attachment".to_owned()),
+            ],
+            Body::from_stream(body),
+        )
+            .into_response()
+    })
+}
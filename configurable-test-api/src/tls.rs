@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use rcgen::CertifiedKey;
+use rustls::{
+    RootCertStore, ServerConfig,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::WebPkiClientVerifier,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::{Protocol, TlsConfig};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum TlsError {
+    #[error("Both cert and key must be configured, or neither for a self-signed certificate")]
+    IncompleteKeyPair,
+    #[error("Failed to read PEM file: {0}, due to error: {1}")]
+    Pem(String, rustls::pki_types::pem::Error),
+    #[error("Failed to generate self-signed certificate: {0}")]
+    SelfSigned(#[from] rcgen::Error),
+    #[error("Failed to add client CA certificate: {0}")]
+    ClientCa(rustls::Error),
+    #[error("Failed to build client certificate verifier: {0}")]
+    ClientVerifier(#[from] rustls::server::VerifierBuilderError),
+    #[error("Failed to build TLS config: {0}")]
+    Config(#[from] rustls::Error),
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::Pem(path.to_owned(), e))
+}
+
+fn read_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| TlsError::Pem(path.to_owned(), e))
+}
+
+fn self_signed(
+    names: Vec<String>,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), TlsError> {
+    let CertifiedKey { cert, signing_key } = rcgen::generate_simple_self_signed(names)?;
+
+    Ok((
+        vec![cert.der().clone()],
+        PrivateKeyDer::try_from(signing_key.serialize_der())
+            .expect("Generated key is a valid PKCS#8 key"),
+    ))
+}
+
+/// Builds the TLS acceptor for the listener, `names` are used as the subject alternative names
+/// of a generated self-signed certificate and `protocol` restricts the ALPN protocols offered.
+pub(crate) fn make_acceptor(
+    config: &TlsConfig,
+    names: Vec<String>,
+    protocol: Protocol,
+) -> Result<TlsAcceptor, TlsError> {
+    let (certs, key) = match (&config.cert, &config.key) {
+        (Some(cert), Some(key)) => (read_certs(cert)?, read_key(key)?),
+        (None, None) => {
+            log::info!(
+                "Generating self-signed certificate for: {}",
+                names.join(", ")
+            );
+
+            self_signed(names)?
+        }
+        _ => Err(TlsError::IncompleteKeyPair)?,
+    };
+
+    let builder = ServerConfig::builder();
+
+    let builder = match &config.ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+
+            for cert in read_certs(ca)? {
+                roots.add(cert).map_err(TlsError::ClientCa)?;
+            }
+
+            builder
+                .with_client_cert_verifier(WebPkiClientVerifier::builder(Arc::new(roots)).build()?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_single_cert(certs, key)?;
+
+    server_config.alpn_protocols = match protocol {
+        Protocol::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        Protocol::Http1 => vec![b"http/1.1".to_vec()],
+        Protocol::Http2 => vec![b"h2".to_vec()],
+    };
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
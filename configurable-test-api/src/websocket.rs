@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use axum::{
+    extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    routing::{MethodRouter, get},
+};
+use tokio::{
+    select,
+    sync::broadcast::{self, Sender, error::RecvError},
+    time::{interval, sleep},
+};
+
+use crate::config::{RouteConfig, WebSocketMode};
+
+/// Counts messages sent to a client and closes the connection once the configured limit is
+/// reached. Returns `false` when the connection should no longer be used.
+async fn send_counted(
+    socket: &mut WebSocket,
+    message: Message,
+    sent: &mut usize,
+    limit: Option<usize>,
+) -> bool {
+    if socket.send(message).await.is_err() {
+        return false;
+    }
+
+    *sent += 1;
+
+    if limit.is_some_and(|l| *sent >= l) {
+        let _ = socket
+            .send(Message::Close(Some(CloseFrame {
+                code: close_code::NORMAL,
+                reason: "message limit reached".into(),
+            })))
+            .await;
+
+        return false;
+    }
+
+    true
+}
+
+async fn echo(mut socket: WebSocket, latency: Duration, limit: Option<usize>) {
+    let mut sent = 0;
+
+    while let Some(Ok(message)) = socket.recv().await {
+        let reply = match message {
+            Message::Text(_) | Message::Binary(_) => message,
+            Message::Close(_) => break,
+            Message::Ping(_) | Message::Pong(_) => continue,
+        };
+
+        sleep(latency).await;
+
+        if !send_counted(&mut socket, reply, &mut sent, limit).await {
+            break;
+        }
+    }
+}
+
+async fn broadcast(mut socket: WebSocket, sender: Sender<String>, limit: Option<usize>) {
+    let mut receiver = sender.subscribe();
+    let mut sent = 0;
+
+    loop {
+        select! {
+            message = receiver.recv() => match message {
+                Ok(message) => {
+                    if !send_counted(&mut socket, Message::Text(message.into()), &mut sent, limit).await {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    log::debug!("WebSocket client lagged, skipped {skipped} messages");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => (),
+            },
+        }
+    }
+}
+
+/// Publishes a message of `size` bytes every `period` to all subscribers of `sender`.
+async fn run_broadcast(sender: Sender<String>, period: Duration, size: usize) {
+    let mut ticker = interval(period);
+
+    for sequence in 0u64.. {
+        ticker.tick().await;
+
+        let mut message = format!("{sequence} ");
+        message.extend(std::iter::repeat_n('x', size.saturating_sub(message.len())));
+
+        // Sending only fails when there are no connected clients.
+        let _ = sender.send(message);
+    }
+}
+
+pub(crate) fn make_websocket_callback<S>(route: &RouteConfig) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let latency = Duration::from_millis(route.latency);
+    let limit = route.websocket.limit;
+
+    match route.websocket.mode {
+        WebSocketMode::Echo => get(async move |upgrade: WebSocketUpgrade| {
+            upgrade.on_upgrade(move |socket| echo(socket, latency, limit))
+        }),
+        WebSocketMode::Broadcast => {
+            let (sender, _) = broadcast::channel(16);
+
+            tokio::spawn(run_broadcast(
+                sender.clone(),
+                Duration::from_millis(route.websocket.interval),
+                route.websocket.size,
+            ));
+
+            get(async move |upgrade: WebSocketUpgrade| {
+                upgrade.on_upgrade(move |socket| broadcast(socket, sender, limit))
+            })
+        }
+    }
+}
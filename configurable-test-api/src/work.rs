@@ -0,0 +1,92 @@
+use std::{
+    collections::VecDeque,
+    hash::{DefaultHasher, Hash, Hasher},
+    hint::{black_box, spin_loop},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use tokio::task::spawn_blocking;
+
+use crate::config::WorkConfig;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Simulated per request work for a single route.
+#[derive(Debug)]
+pub(crate) struct Work {
+    hashes: u64,
+    spin: Duration,
+    memory: usize,
+    retain: usize,
+    retained: Mutex<VecDeque<Vec<u8>>>,
+}
+
+impl Work {
+    pub(crate) fn new(config: &WorkConfig) -> Arc<Self> {
+        Arc::new(Self {
+            hashes: config.hashes,
+            spin: Duration::from_micros(config.spin),
+            memory: config.memory * 1024,
+            retain: config.retain,
+            retained: Mutex::new(VecDeque::with_capacity(config.retain)),
+        })
+    }
+
+    fn perform(&self) {
+        let mut hash = 0u64;
+
+        for idx in 0..self.hashes {
+            let mut hasher = DefaultHasher::new();
+            (hash, idx).hash(&mut hasher);
+            hash = hasher.finish();
+        }
+
+        black_box(hash);
+
+        let start = Instant::now();
+
+        while start.elapsed() < self.spin {
+            spin_loop();
+        }
+
+        if self.memory > 0 {
+            // Zeroed allocations may be lazily mapped, write every page so the memory is resident.
+            let mut buffer = vec![0u8; self.memory];
+
+            for idx in (0..buffer.len()).step_by(PAGE_SIZE) {
+                buffer[idx] = 1;
+            }
+
+            let buffer = black_box(buffer);
+
+            if self.retain > 0 {
+                let mut retained = self.retained.lock().expect("Failed to aquire lock");
+
+                if retained.len() >= self.retain {
+                    retained.pop_front();
+                }
+
+                retained.push_back(buffer);
+            }
+        }
+    }
+}
+
+/// Middleware performing the configured work before the request is handled.
+pub(crate) async fn simulate_work(
+    State(work): State<Arc<Work>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    spawn_blocking(move || work.perform())
+        .await
+        .expect("Simulated work panicked");
+
+    next.run(request).await
+}
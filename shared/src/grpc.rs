@@ -0,0 +1,44 @@
+//! Messages and method paths of the `testapi.Echo` gRPC service served by the test API.
+//!
+//! The messages are written by hand rather than generated from a `.proto` file so neither crate
+//! needs `protoc` to build. The equivalent definition is:
+//!
+//! ```proto
+//! syntax = "proto3";
+//! package testapi;
+//!
+//! service Echo {
+//!   rpc Unary(EchoRequest) returns (EchoResponse);
+//!   rpc ServerStream(EchoRequest) returns (stream EchoResponse);
+//! }
+//!
+//! message EchoRequest {
+//!   string message = 1;
+//!   uint32 count = 2;
+//! }
+//!
+//! message EchoResponse {
+//!   string message = 1;
+//!   string instance = 2;
+//! }
+//! ```
+
+pub const UNARY_PATH: &str = "/testapi.Echo/Unary";
+pub const SERVER_STREAM_PATH: &str = "/testapi.Echo/ServerStream";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EchoRequest {
+    #[prost(string, tag = "1")]
+    pub message: String,
+    /// Number of responses requested from `ServerStream`. Ignored by `Unary`.
+    #[prost(uint32, tag = "2")]
+    pub count: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EchoResponse {
+    #[prost(string, tag = "1")]
+    pub message: String,
+    #[prost(string, tag = "2")]
+    pub instance: String,
+}
@@ -0,0 +1,92 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use http_body_util::{BodyExt, Empty, Full, combinators::BoxBody};
+use hyper::body::{Body, Bytes, Frame, Incoming, SizeHint};
+
+/// Body of responses sent to clients, streamed from a target or generated by the load balancer.
+pub type ResponseBody = BoxBody<Bytes, hyper::Error>;
+
+pub fn full(bytes: Bytes) -> BoxBody<Bytes, hyper::Error> {
+    Full::new(bytes).map_err(|never| match never {}).boxed()
+}
+
+pub fn empty() -> BoxBody<Bytes, hyper::Error> {
+    Empty::new().map_err(|never| match never {}).boxed()
+}
+
+type OnEnd = Box<dyn FnOnce(bool) + Send + Sync>;
+
+/// Response body streamed from a target. `on_end` runs exactly once, with `true` when the body
+/// completed and with `false` when it failed or the client went away before reading all of it.
+pub struct StreamingBody {
+    inner: Incoming,
+    on_end: Option<OnEnd>,
+}
+
+impl StreamingBody {
+    pub fn new(inner: Incoming, on_end: impl FnOnce(bool) + Send + Sync + 'static) -> Self {
+        // Empty bodies may never be polled.
+        if inner.is_end_stream() {
+            on_end(true);
+
+            return Self {
+                inner,
+                on_end: None,
+            };
+        }
+
+        Self {
+            inner,
+            on_end: Some(Box::new(on_end)),
+        }
+    }
+}
+
+impl Body for StreamingBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+
+        match &polled {
+            // The body is not polled again once it reports its end.
+            Poll::Ready(Some(Ok(_))) if !self.inner.is_end_stream() => (),
+            Poll::Ready(Some(Ok(_))) | Poll::Ready(None) => {
+                if let Some(on_end) = self.on_end.take() {
+                    on_end(true);
+                }
+            }
+            Poll::Ready(Some(Err(_))) => {
+                if let Some(on_end) = self.on_end.take() {
+                    on_end(false);
+                }
+            }
+            _ => (),
+        }
+
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for StreamingBody {
+    fn drop(&mut self) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(false);
+        }
+    }
+}
@@ -1,25 +1,33 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use dashmap::DashMap;
-use http::Response;
+use http::{Response, response::Parts};
 use http_body_util::Full;
-use hyper::body::Bytes;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
 use tokio::{
     spawn,
     time::{Instant, sleep},
 };
 
-#[derive()]
+use crate::body::ResponseBody;
+
 pub struct RequestCache {
     inner: DashMap<String, CachedResponse>,
     ttl: Duration,
+    max_body_size: usize,
 }
 
 impl RequestCache {
-    pub fn new(ttl: Duration) -> Arc<Self> {
+    pub fn new(ttl: Duration, max_body_size: usize) -> Arc<Self> {
         let self_arc = Arc::new(Self {
             inner: Default::default(),
             ttl,
+            max_body_size,
         });
 
         spawn(self_arc.clone().cleanup_thread());
@@ -31,9 +39,39 @@ impl RequestCache {
         self.inner.get(key).map(|e| e.inner.clone())
     }
 
-    pub fn set(&self, key: &str, request: &Response<Full<Bytes>>) {
+    pub fn set(&self, key: &str, request: Response<Full<Bytes>>) {
         self.inner
-            .insert(key.to_owned(), CachedResponse::new(request.clone()));
+            .insert(key.to_owned(), CachedResponse::new(request));
+    }
+
+    /// Streams `response` to the client while copying its body, the response is cached once
+    /// the body completed within the maximum body size.
+    pub fn tee(
+        self: &Arc<Self>,
+        key: &str,
+        response: Response<ResponseBody>,
+    ) -> Response<ResponseBody> {
+        let (parts, body) = response.into_parts();
+
+        // Empty bodies may never be polled.
+        if body.is_end_stream() {
+            self.set(
+                key,
+                Response::from_parts(clone_head(&parts), Full::new(Bytes::new())),
+            );
+
+            return Response::from_parts(parts, body);
+        }
+
+        let body = CachingBody {
+            inner: body,
+            cache: self.clone(),
+            key: key.to_owned(),
+            head: Some(clone_head(&parts)),
+            buffer: Vec::new(),
+        };
+
+        Response::from_parts(parts, ResponseBody::new(body))
     }
 
     async fn cleanup_thread(self: Arc<Self>) {
@@ -62,3 +100,78 @@ impl CachedResponse {
         self.set_time.elapsed() > ttl
     }
 }
+
+/// Response parts without the extensions, which cannot be cloned.
+fn clone_head(parts: &Parts) -> Parts {
+    let mut head = Response::new(());
+    *head.status_mut() = parts.status;
+    *head.version_mut() = parts.version;
+    *head.headers_mut() = parts.headers.clone();
+
+    head.into_parts().0
+}
+
+struct CachingBody {
+    inner: ResponseBody,
+    cache: Arc<RequestCache>,
+    key: String,
+    // Taken once the body is cached or became too large to cache.
+    head: Option<Parts>,
+    buffer: Vec<u8>,
+}
+
+impl CachingBody {
+    fn store(&mut self) {
+        if let Some(head) = self.head.take() {
+            let body = Bytes::from(std::mem::take(&mut self.buffer));
+
+            self.cache
+                .set(&self.key, Response::from_parts(head, Full::new(body)));
+        }
+    }
+}
+
+impl Body for CachingBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    if self.buffer.len() + data.len() > self.cache.max_body_size {
+                        self.head = None;
+                        self.buffer = Vec::new();
+                    } else if self.head.is_some() {
+                        self.buffer.extend_from_slice(data);
+                    }
+                }
+
+                // The body is not polled again once it reports its end.
+                if self.inner.is_end_stream() {
+                    self.store();
+                }
+            }
+            Poll::Ready(Some(Err(_))) => {
+                self.head = None;
+            }
+            Poll::Ready(None) => self.store(),
+            Poll::Pending => (),
+        }
+
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::{
+    config::TargetGroupCircuitBreakerConfiguration,
+    connection_pool::{TargetConnectionPool, UpstreamBody},
+};
+
+#[derive(Debug)]
+enum CircuitState {
+    Closed { consecutive_failures: usize },
+    Open { until: Instant },
+    HalfOpen { probes: usize },
+}
+
+/// Circuit state and requests in flight of a single target, kept with its connection pool so it
+/// follows the target when the health monitor moves it in and out of the target group.
+#[derive(Debug)]
+pub struct TargetCircuitStatus {
+    state: Mutex<CircuitState>,
+    pending: AtomicUsize,
+    active: AtomicUsize,
+}
+
+impl TargetCircuitStatus {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(CircuitState::Closed {
+                consecutive_failures: 0,
+            }),
+            pending: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Default for TargetCircuitStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn increment_below(counter: &AtomicUsize, limit: Option<usize>) -> bool {
+    counter
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+            limit.is_none_or(|l| count < l).then_some(count + 1)
+        })
+        .is_ok()
+}
+
+/// Admission of one request to a target, released when dropped.
+pub struct CircuitPermit {
+    status: Arc<TargetCircuitStatus>,
+    probe: bool,
+}
+
+impl Drop for CircuitPermit {
+    fn drop(&mut self) {
+        self.status.active.fetch_sub(1, Ordering::AcqRel);
+
+        if self.probe
+            && let CircuitState::HalfOpen { probes } = &mut *self
+                .status
+                .state
+                .lock()
+                .expect("Failed to aquire circuit state lock")
+        {
+            *probes = probes.saturating_sub(1);
+        }
+    }
+}
+
+/// A request waiting for a connection to its target, released when dropped.
+pub struct PendingRequest<'a>(&'a AtomicUsize);
+
+impl Drop for PendingRequest<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+pub struct CircuitBreaker {
+    max_pending: Option<usize>,
+    max_requests: Option<usize>,
+    consecutive_failures: usize,
+    cool_down: Duration,
+    half_open_requests: usize,
+}
+
+impl From<&TargetGroupCircuitBreakerConfiguration> for CircuitBreaker {
+    fn from(value: &TargetGroupCircuitBreakerConfiguration) -> Self {
+        Self {
+            max_pending: value.max_pending,
+            max_requests: value.max_requests,
+            consecutive_failures: value.consecutive_failures,
+            cool_down: Duration::from_millis(value.cool_down),
+            // Without probes a half open circuit would never close again.
+            half_open_requests: value.half_open_requests.max(1),
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// Admits a request to `target`, or returns `None` when its circuit is open or it already
+    /// serves the maximum number of concurrent requests.
+    pub fn try_acquire(
+        &self,
+        target: &TargetConnectionPool<UpstreamBody>,
+    ) -> Option<CircuitPermit> {
+        let status = &target.circuit_status;
+        let mut state = status
+            .state
+            .lock()
+            .expect("Failed to aquire circuit state lock");
+
+        if let CircuitState::Open { until } = *state {
+            if until > Instant::now() {
+                return None;
+            }
+
+            log::info!("Circuit of target: {} is half open", target._socket_addr);
+
+            *state = CircuitState::HalfOpen { probes: 0 };
+        }
+
+        if let CircuitState::HalfOpen { probes } = *state
+            && probes >= self.half_open_requests
+        {
+            return None;
+        }
+
+        if !increment_below(&status.active, self.max_requests) {
+            return None;
+        }
+
+        let probe = match &mut *state {
+            CircuitState::HalfOpen { probes } => {
+                *probes += 1;
+                true
+            }
+            _ => false,
+        };
+
+        Some(CircuitPermit {
+            status: status.clone(),
+            probe,
+        })
+    }
+
+    /// Counts a request waiting for a pooled connection, or returns `None` when too many already
+    /// wait and the request should fail fast instead of queueing.
+    pub fn start_pending<'a>(
+        &self,
+        target: &'a TargetConnectionPool<UpstreamBody>,
+    ) -> Option<PendingRequest<'a>> {
+        let pending = &target.circuit_status.pending;
+
+        increment_below(pending, self.max_pending).then(|| PendingRequest(pending))
+    }
+
+    pub fn record(&self, target: &TargetConnectionPool<UpstreamBody>, is_error: bool) {
+        let mut state = target
+            .circuit_status
+            .state
+            .lock()
+            .expect("Failed to aquire circuit state lock");
+
+        let opened = match &mut *state {
+            CircuitState::Closed {
+                consecutive_failures,
+            } => {
+                if is_error {
+                    *consecutive_failures += 1;
+                } else {
+                    *consecutive_failures = 0;
+                }
+
+                *consecutive_failures >= self.consecutive_failures
+            }
+            CircuitState::HalfOpen { .. } => {
+                if !is_error {
+                    log::info!("Circuit of target: {} is closed", target._socket_addr);
+
+                    *state = CircuitState::Closed {
+                        consecutive_failures: 0,
+                    };
+                }
+
+                is_error
+            }
+            CircuitState::Open { .. } => false,
+        };
+
+        if opened {
+            log::warn!(
+                "Circuit of target: {} is open for {}ms",
+                target._socket_addr,
+                self.cool_down.as_millis()
+            );
+
+            *state = CircuitState::Open {
+                until: Instant::now() + self.cool_down,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::test_util::target_group_pools;
+
+    fn breaker(
+        configure: impl FnOnce(&mut TargetGroupCircuitBreakerConfiguration),
+    ) -> CircuitBreaker {
+        let mut config = TargetGroupCircuitBreakerConfiguration {
+            consecutive_failures: 2,
+            cool_down: 100,
+            half_open_requests: 1,
+            ..Default::default()
+        };
+        configure(&mut config);
+
+        CircuitBreaker::from(&config)
+    }
+
+    async fn target() -> TargetConnectionPool<UpstreamBody> {
+        let pools = target_group_pools(&[SocketAddr::from(([127, 0, 0, 1], 1))]).await;
+
+        pools.write().await.remove(0)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_half_opens_and_closes() {
+        let breaker = breaker(|_| ());
+        let target = target().await;
+
+        breaker.record(&target, true);
+        breaker.record(&target, false);
+        breaker.record(&target, true);
+        assert!(breaker.try_acquire(&target).is_some());
+
+        breaker.record(&target, true);
+        assert!(breaker.try_acquire(&target).is_none());
+
+        tokio::time::advance(Duration::from_millis(100)).await;
+
+        // Only `half_open_requests` probes are let through until one of them completes.
+        let probe = breaker.try_acquire(&target);
+        assert!(probe.is_some());
+        assert!(breaker.try_acquire(&target).is_none());
+
+        breaker.record(&target, false);
+        drop(probe);
+
+        assert!(breaker.try_acquire(&target).is_some());
+        assert!(breaker.try_acquire(&target).is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_probe_opens_again() {
+        let breaker = breaker(|_| ());
+        let target = target().await;
+
+        breaker.record(&target, true);
+        breaker.record(&target, true);
+        tokio::time::advance(Duration::from_millis(100)).await;
+
+        let probe = breaker.try_acquire(&target);
+        assert!(probe.is_some());
+        breaker.record(&target, true);
+        drop(probe);
+
+        assert!(breaker.try_acquire(&target).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_half_open_requests_still_probes() {
+        let breaker = breaker(|c| c.half_open_requests = 0);
+        let target = target().await;
+
+        breaker.record(&target, true);
+        breaker.record(&target, true);
+        tokio::time::advance(Duration::from_millis(100)).await;
+
+        assert!(breaker.try_acquire(&target).is_some());
+    }
+
+    #[tokio::test]
+    async fn fails_fast_beyond_max_requests() {
+        let breaker = breaker(|c| c.max_requests = Some(2));
+        let target = target().await;
+
+        let first = breaker.try_acquire(&target);
+        let second = breaker.try_acquire(&target);
+        assert!(first.is_some() && second.is_some());
+        assert!(breaker.try_acquire(&target).is_none());
+
+        drop(first);
+        assert!(breaker.try_acquire(&target).is_some());
+    }
+
+    #[tokio::test]
+    async fn fails_fast_beyond_max_pending() {
+        let breaker = breaker(|c| c.max_pending = Some(1));
+        let target = target().await;
+
+        let pending = breaker.start_pending(&target);
+        assert!(pending.is_some());
+        assert!(breaker.start_pending(&target).is_none());
+
+        drop(pending);
+        assert!(breaker.start_pending(&target).is_some());
+    }
+}
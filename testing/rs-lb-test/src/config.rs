@@ -26,6 +26,14 @@ fn default_cache_ttl() -> u64 {
     10000
 }
 
+fn default_cache_max_body_size() -> usize {
+    1048576
+}
+
+fn default_listener_tls() -> ListenerTlsConfiguration {
+    Default::default()
+}
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct LoadBalancerConfiguration {
     #[serde(default = "default_listener_port")]
@@ -40,6 +48,11 @@ pub(crate) struct LoadBalancerConfiguration {
     pub cache_enabled: bool,
     #[serde(default = "default_cache_ttl")]
     pub cache_ttl: u64,
+    /// Larger responses are streamed without being cached.
+    #[serde(default = "default_cache_max_body_size")]
+    pub cache_max_body_size: usize,
+    #[serde(default = "default_listener_tls")]
+    pub tls: ListenerTlsConfiguration,
     pub listener_rules: HashMap<String, ListenerRuleConfiguration>,
     pub target_groups: HashMap<String, TargetGroupConfiguration>,
 }
@@ -48,12 +61,13 @@ impl Display for LoadBalancerConfiguration {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(
             format!(
-                "LoadBalancerConfiguration{{\n\tlistener_port={},\n\tconnection_timout={},\n\tload_balancing_algorithm={},\n\tconnection_pool_size={},\n\tcache_enabled={},\n",
+                "LoadBalancerConfiguration{{\n\tlistener_port={},\n\tconnection_timout={},\n\tload_balancing_algorithm={},\n\tconnection_pool_size={},\n\tcache_enabled={},\n\ttls_enabled={},\n",
                 self.listener_port,
                 self.connection_timout,
                 self.load_balancing_algorithm,
                 self.connection_pool_size,
-                self.cache_enabled
+                self.cache_enabled,
+                self.tls.enabled
             )
             .as_ref(),
         )?;
@@ -70,22 +84,78 @@ impl Display for LoadBalancerConfiguration {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize)]
 pub enum LoadBalancingAlgorithm {
     #[serde(alias = "ROUND_ROBIN")]
     RoundRobin,
+    #[serde(alias = "WEIGHTED", alias = "WEIGHTED_ROUND_ROBIN")]
+    WeightedRoundRobin,
+    #[serde(alias = "LEAST_REQUESTS", alias = "LEAST_CONNECTIONS")]
+    LeastRequests,
+    #[serde(alias = "LRT", alias = "LEAST_RESPONSE_TIME")]
+    LeastResponseTime,
+    #[serde(alias = "RANDOM")]
+    Random,
+    #[serde(alias = "P2C", alias = "POWER_OF_TWO_CHOICES")]
+    PowerOfTwoChoices,
+    /// Round robin for new clients, which then stay on their target through `COOKIE` session
+    /// affinity unless the target group configures another affinity mode.
+    #[serde(alias = "STICKY")]
+    Sticky,
 }
 
 impl Display for LoadBalancingAlgorithm {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let variant = match self {
             Self::RoundRobin => "ROUND_ROBIN",
+            Self::WeightedRoundRobin => "WEIGHTED_ROUND_ROBIN",
+            Self::LeastRequests => "LEAST_REQUESTS",
+            Self::LeastResponseTime => "LEAST_RESPONSE_TIME",
+            Self::Random => "RANDOM",
+            Self::PowerOfTwoChoices => "POWER_OF_TWO_CHOICES",
+            Self::Sticky => "STICKY",
         };
 
         f.write_str(format!("LoadBalancingAlgorithm::{}", variant).as_ref())
     }
 }
 
+fn default_reload_interval() -> u64 {
+    5000
+}
+
+/// Terminates TLS on the listener, HTTP/2 is negotiated through ALPN. The certificate for a
+/// connection is selected by matching the SNI name against the `server_names` of each
+/// certificate, wildcards like `*.example.com` included, falling back to the certificate without
+/// `server_names`. Certificate and key files are checked for changes every `reload_interval`.
+#[derive(Debug, Deserialize)]
+pub struct ListenerTlsConfiguration {
+    #[serde(default = "default_enable")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub certificates: HashMap<String, ListenerCertificateConfiguration>,
+    #[serde(default = "default_reload_interval")]
+    pub reload_interval: u64,
+}
+
+impl Default for ListenerTlsConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: default_enable(),
+            certificates: HashMap::new(),
+            reload_interval: default_reload_interval(),
+        }
+    }
+}
+
+/// PEM encoded certificate chain and private key, `server_names` is a comma separated list.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ListenerCertificateConfiguration {
+    pub cert_path: String,
+    pub key_path: String,
+    pub server_names: Option<String>,
+}
+
 fn default_path_rewrite() -> String {
     "".to_owned()
 }
@@ -96,37 +166,186 @@ pub struct ListenerRuleConfiguration {
     pub path_prefix: String,
     #[serde(default = "default_path_rewrite")]
     pub path_rewrite: String,
+    #[serde(default = "default_retry")]
+    pub retry: ListenerRuleRetryConfiguration,
 }
 
 impl Display for ListenerRuleConfiguration {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(
             format!(
-                "ListenerRuleConfiguration{{target_group={}, path_prefix={}, path_rewrite={}}}",
-                self.target_group, self.path_prefix, self.path_rewrite
+                "ListenerRuleConfiguration{{target_group={}, path_prefix={}, path_rewrite={}, retry.attempts={}}}",
+                self.target_group, self.path_prefix, self.path_rewrite, self.retry.attempts
             )
             .as_ref(),
         )
     }
 }
 
+fn default_retry() -> ListenerRuleRetryConfiguration {
+    Default::default()
+}
+
+fn default_attempts() -> usize {
+    1
+}
+fn default_allow_non_idempotent() -> bool {
+    false
+}
+fn default_max_body_size() -> u64 {
+    65536
+}
+fn default_budget_percent() -> usize {
+    20
+}
+fn default_min_retry_concurrency() -> usize {
+    3
+}
+
+/// Retries of requests failing to connect, reset by the target, timing out or answered with
+/// `502`, `503` or `504`. Each of the `attempts` goes to a different target and is limited by
+/// `per_try_timeout`, bounded overall by the connection timeout. Only idempotent methods with a
+/// body of at most `max_body_size` bytes are retried unless `allow_non_idempotent` is set.
+/// Retries in flight are limited to `budget_percent` of the rule's active requests, but at
+/// least `min_retry_concurrency`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ListenerRuleRetryConfiguration {
+    #[serde(default = "default_attempts")]
+    pub attempts: usize,
+    pub per_try_timeout: Option<u64>,
+    #[serde(default = "default_allow_non_idempotent")]
+    pub allow_non_idempotent: bool,
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: u64,
+    #[serde(default = "default_budget_percent")]
+    pub budget_percent: usize,
+    #[serde(default = "default_min_retry_concurrency")]
+    pub min_retry_concurrency: usize,
+}
+
+impl Default for ListenerRuleRetryConfiguration {
+    fn default() -> Self {
+        Self {
+            attempts: default_attempts(),
+            per_try_timeout: None,
+            allow_non_idempotent: default_allow_non_idempotent(),
+            max_body_size: default_max_body_size(),
+            budget_percent: default_budget_percent(),
+            min_retry_concurrency: default_min_retry_concurrency(),
+        }
+    }
+}
+
 fn default_health_check() -> TargetGroupHealthCheckConfiguration {
     Default::default()
 }
 
+fn default_protocol() -> UpstreamProtocol {
+    UpstreamProtocol::Http1
+}
+
+/// `targets` is a comma separated list of `hostname:port[/uri][;weight=N]`, weights only affect
+/// the `WEIGHTED_ROUND_ROBIN` algorithm. Without a `load_balancing_algorithm` the group uses the
+/// load balancer's default.
 #[derive(Debug, Deserialize)]
 pub struct TargetGroupConfiguration {
     pub targets: String,
+    pub load_balancing_algorithm: Option<LoadBalancingAlgorithm>,
+    #[serde(default = "default_protocol")]
+    pub protocol: UpstreamProtocol,
+    #[serde(default = "default_target_group_tls")]
+    pub tls: TargetGroupTlsConfiguration,
     #[serde(default = "default_health_check")]
     pub health_check: TargetGroupHealthCheckConfiguration,
+    #[serde(default = "default_outlier_detection")]
+    pub outlier_detection: TargetGroupOutlierDetectionConfiguration,
+    #[serde(default = "default_session_affinity")]
+    pub session_affinity: TargetGroupSessionAffinityConfiguration,
+    #[serde(default = "default_circuit_breaker")]
+    pub circuit_breaker: TargetGroupCircuitBreakerConfiguration,
 }
 
 impl Display for TargetGroupConfiguration {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(format!("TargetGroupConfiguration{{targets={}}}", self.targets).as_ref())
+        f.write_str(
+            format!(
+                "TargetGroupConfiguration{{targets={}, load_balancing_algorithm={}, protocol={}, tls_enabled={}, session_affinity={:?}}}",
+                self.targets,
+                self.load_balancing_algorithm
+                    .map(|a| a.to_string())
+                    .unwrap_or("DEFAULT".to_owned()),
+                self.protocol,
+                self.tls.enabled,
+                self.session_affinity.mode
+            )
+            .as_ref(),
+        )
+    }
+}
+
+/// Protocol spoken to the targets of a group. HTTP/1 targets get a pool of connections, HTTP/2
+/// targets a single connection multiplexing all requests, negotiated through ALPN when the group
+/// uses TLS and with prior knowledge otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum UpstreamProtocol {
+    #[serde(alias = "HTTP1", alias = "HTTP/1.1")]
+    Http1,
+    #[serde(alias = "HTTP2", alias = "H2", alias = "H2C")]
+    Http2,
+}
+
+impl Display for UpstreamProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let variant = match self {
+            Self::Http1 => "HTTP1",
+            Self::Http2 => "HTTP2",
+        };
+
+        f.write_str(format!("UpstreamProtocol::{}", variant).as_ref())
+    }
+}
+
+fn default_target_group_tls() -> TargetGroupTlsConfiguration {
+    Default::default()
+}
+
+/// TLS to the targets of the group, verified against the PEM bundle at `ca_path` or the webpki
+/// roots without it. The SNI name and verified name is the target's hostname unless
+/// `server_name` overrides it, `client_cert_path` and `client_key_path` enable mTLS.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TargetGroupTlsConfiguration {
+    #[serde(default = "default_enable")]
+    pub enabled: bool,
+    pub ca_path: Option<String>,
+    pub server_name: Option<String>,
+    pub client_cert_path: Option<String>,
+    pub client_key_path: Option<String>,
+}
+
+impl Default for TargetGroupTlsConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: default_enable(),
+            ca_path: None,
+            server_name: None,
+            client_cert_path: None,
+            client_key_path: None,
+        }
     }
 }
 
+fn default_outlier_detection() -> TargetGroupOutlierDetectionConfiguration {
+    Default::default()
+}
+
+fn default_session_affinity() -> TargetGroupSessionAffinityConfiguration {
+    Default::default()
+}
+
+fn default_circuit_breaker() -> TargetGroupCircuitBreakerConfiguration {
+    Default::default()
+}
+
 fn default_enable() -> bool {
     false
 }
@@ -170,3 +389,157 @@ impl Default for TargetGroupHealthCheckConfiguration {
         }
     }
 }
+
+fn default_consecutive_errors() -> usize {
+    5
+}
+fn default_error_rate_window() -> u64 {
+    10000
+}
+fn default_error_rate_minimum_requests() -> usize {
+    10
+}
+fn default_base_ejection_time() -> u64 {
+    30000
+}
+fn default_max_ejection_time() -> u64 {
+    300000
+}
+fn default_max_ejection_percent() -> usize {
+    50
+}
+
+/// Passive health checking based on the responses of proxied requests. A target is ejected
+/// after `consecutive_errors` 5xx responses, connection failures or timeouts in a row, or when
+/// its error rate over `error_rate_window` reaches `error_rate` percent.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TargetGroupOutlierDetectionConfiguration {
+    #[serde(default = "default_enable")]
+    pub enabled: bool,
+    #[serde(default = "default_consecutive_errors")]
+    pub consecutive_errors: usize,
+    pub error_rate: Option<usize>,
+    #[serde(default = "default_error_rate_window")]
+    pub error_rate_window: u64,
+    #[serde(default = "default_error_rate_minimum_requests")]
+    pub error_rate_minimum_requests: usize,
+    /// Ejection time of the first ejection, doubled for each further ejection of the target.
+    #[serde(default = "default_base_ejection_time")]
+    pub base_ejection_time: u64,
+    #[serde(default = "default_max_ejection_time")]
+    pub max_ejection_time: u64,
+    /// Maximum share of the target group that can be ejected at once.
+    #[serde(default = "default_max_ejection_percent")]
+    pub max_ejection_percent: usize,
+}
+
+impl Default for TargetGroupOutlierDetectionConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: default_enable(),
+            consecutive_errors: default_consecutive_errors(),
+            error_rate: None,
+            error_rate_window: default_error_rate_window(),
+            error_rate_minimum_requests: default_error_rate_minimum_requests(),
+            base_ejection_time: default_base_ejection_time(),
+            max_ejection_time: default_max_ejection_time(),
+            max_ejection_percent: default_max_ejection_percent(),
+        }
+    }
+}
+
+fn default_session_affinity_mode() -> SessionAffinityMode {
+    SessionAffinityMode::None
+}
+fn default_cookie_name() -> String {
+    "LB_SESSION".to_owned()
+}
+fn default_session_ttl() -> u64 {
+    30000
+}
+fn default_virtual_nodes() -> usize {
+    100
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SessionAffinityMode {
+    #[serde(alias = "NONE")]
+    None,
+    #[serde(alias = "COOKIE")]
+    Cookie,
+    #[serde(alias = "CLIENT_IP")]
+    ClientIp,
+    #[serde(alias = "HEADER")]
+    Header,
+}
+
+/// Keeps requests of a session on the same target. `COOKIE` issues a cookie naming the target
+/// which expires after `session_ttl`, `CLIENT_IP` and `HEADER` hash the client IP or the value of
+/// `header` onto a consistent hash ring with `virtual_nodes` points per unit of target weight.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TargetGroupSessionAffinityConfiguration {
+    #[serde(default = "default_session_affinity_mode")]
+    pub mode: SessionAffinityMode,
+    #[serde(default = "default_cookie_name")]
+    pub cookie_name: String,
+    #[serde(default = "default_session_ttl")]
+    pub session_ttl: u64,
+    pub header: Option<String>,
+    #[serde(default = "default_virtual_nodes")]
+    pub virtual_nodes: usize,
+}
+
+impl Default for TargetGroupSessionAffinityConfiguration {
+    fn default() -> Self {
+        Self {
+            mode: default_session_affinity_mode(),
+            cookie_name: default_cookie_name(),
+            session_ttl: default_session_ttl(),
+            header: None,
+            virtual_nodes: default_virtual_nodes(),
+        }
+    }
+}
+
+fn default_consecutive_failures() -> usize {
+    5
+}
+fn default_cool_down() -> u64 {
+    10000
+}
+fn default_half_open_requests() -> usize {
+    1
+}
+
+/// Limits applied to every target of the group. Requests beyond `max_requests` concurrent ones
+/// or `max_pending` waiting for a connection fail fast with `503`, `max_connections` caps the
+/// target's connection pool. After `consecutive_failures` failed requests the circuit opens
+/// for `cool_down`, then lets `half_open_requests` probes through to decide whether to close.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TargetGroupCircuitBreakerConfiguration {
+    #[serde(default = "default_enable")]
+    pub enabled: bool,
+    pub max_pending: Option<usize>,
+    pub max_requests: Option<usize>,
+    pub max_connections: Option<u32>,
+    #[serde(default = "default_consecutive_failures")]
+    pub consecutive_failures: usize,
+    #[serde(default = "default_cool_down")]
+    pub cool_down: u64,
+    #[serde(default = "default_half_open_requests")]
+    pub half_open_requests: usize,
+}
+
+impl Default for TargetGroupCircuitBreakerConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: default_enable(),
+            max_pending: None,
+            max_requests: None,
+            max_connections: None,
+            consecutive_failures: default_consecutive_failures(),
+            cool_down: default_cool_down(),
+            half_open_requests: default_half_open_requests(),
+        }
+    }
+}
@@ -1,9 +1,18 @@
-use std::{marker::PhantomData, net::SocketAddr};
+use std::{
+    marker::PhantomData,
+    net::SocketAddr,
+    ops::{Deref, DerefMut},
+};
 
 use bb8::ManageConnection;
-use hyper::{body::Body, client::conn::http1::SendRequest};
-use hyper_util::rt::TokioIo;
-use tokio::net::TcpStream;
+use hyper::{
+    body::Body,
+    client::conn::{http1::SendRequest, http2},
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::{net::TcpStream, sync::Mutex};
+
+use crate::tls::{TargetTls, UpstreamStream};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ConnectionManagerError {
@@ -13,52 +22,104 @@ pub enum ConnectionManagerError {
     ConnectionClosed,
     #[error(transparent)]
     UnableToConnect(std::io::Error),
+    #[error("TLS handshake failed: {0}")]
+    TlsHandshake(std::io::Error),
 }
 
 #[derive(Debug, Clone)]
 pub struct ConnectionManager<T> {
     addr: SocketAddr,
+    tls: Option<TargetTls>,
     _phantom_type: PhantomData<T>,
 }
 
 impl<T> ConnectionManager<T> {
-    pub fn new(addr: SocketAddr) -> Self {
+    pub fn new(addr: SocketAddr, tls: Option<TargetTls>) -> Self {
         Self {
             addr,
+            tls,
             _phantom_type: Default::default(),
         }
     }
 }
 
+/// HTTP/1 connection held by the pool. A connection whose response was abandoned half read can
+/// not carry another request, it is discarded so the pool closes it instead of reusing it.
+#[derive(Debug)]
+pub struct PooledSender<T> {
+    sender: SendRequest<T>,
+    discarded: bool,
+}
+
+impl<T> PooledSender<T> {
+    pub fn discard(&mut self) {
+        self.discarded = true;
+    }
+}
+
+impl<T> Deref for PooledSender<T> {
+    type Target = SendRequest<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.sender
+    }
+}
+
+impl<T> DerefMut for PooledSender<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.sender
+    }
+}
+
+async fn connect_stream(
+    addr: SocketAddr,
+    tls: Option<&TargetTls>,
+) -> Result<TokioIo<UpstreamStream>, ConnectionManagerError> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .map_err(ConnectionManagerError::UnableToConnect)?;
+
+    let stream = match tls {
+        Some(tls) => UpstreamStream::Tls(Box::new(
+            tls.connect(stream)
+                .await
+                .map_err(ConnectionManagerError::TlsHandshake)?,
+        )),
+        None => UpstreamStream::Plain(stream),
+    };
+
+    Ok(TokioIo::new(stream))
+}
+
 impl<T> ManageConnection for ConnectionManager<T>
 where
     T: Send + Sync + Body + 'static,
     T::Data: Send,
     T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
 {
-    type Connection = SendRequest<T>;
+    type Connection = PooledSender<T>;
     type Error = ConnectionManagerError;
 
     async fn connect(&self) -> Result<Self::Connection, Self::Error> {
-        let stream = TcpStream::connect(self.addr)
-            .await
-            .map_err(ConnectionManagerError::UnableToConnect)?;
-
-        let io = TokioIo::new(stream);
+        let io = connect_stream(self.addr, self.tls.as_ref()).await?;
 
         let (sender, conn) = hyper::client::conn::http1::Builder::new()
             .handshake::<_, T>(io)
             .await
             .map_err(ConnectionManagerError::HyperError)?;
 
+        let addr = self.addr;
+
         tokio::task::spawn(async move {
-            match conn.await {
-                Ok(_) => (),
-                Result::Err(err) => println!("Connection failed: {:?}", err),
+            if let Err(e) = conn.await {
+                log::error!("Connection to target: {} failed, error: {}", addr, e);
             }
         });
 
-        Ok(sender)
+        Ok(PooledSender {
+            sender,
+            discarded: false,
+        })
     }
 
     async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
@@ -70,6 +131,89 @@ where
     }
 
     fn has_broken(&self, conn: &mut Self::Connection) -> bool {
-        conn.is_closed()
+        conn.discarded || conn.is_closed()
+    }
+}
+
+/// A single HTTP/2 connection to a target shared by all requests, which are multiplexed over it.
+/// The connection is established on first use and again once it closed.
+#[derive(Debug)]
+pub struct MultiplexedConnection<T> {
+    addr: SocketAddr,
+    tls: Option<TargetTls>,
+    sender: Mutex<Option<http2::SendRequest<T>>>,
+}
+
+impl<T> MultiplexedConnection<T>
+where
+    T: Send + Sync + Body + Unpin + 'static,
+    T::Data: Send,
+    T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
+{
+    pub fn new(addr: SocketAddr, tls: Option<TargetTls>) -> Self {
+        Self {
+            addr,
+            tls,
+            sender: Mutex::new(None),
+        }
+    }
+
+    pub async fn get(&self) -> Result<http2::SendRequest<T>, ConnectionManagerError> {
+        // Held while connecting, so concurrent requests wait for the same connection.
+        let mut sender = self.sender.lock().await;
+
+        if let Some(sender) = sender.as_ref().filter(|s| !s.is_closed()) {
+            return Ok(sender.clone());
+        }
+
+        let io = connect_stream(self.addr, self.tls.as_ref()).await?;
+
+        let (new_sender, conn) = http2::Builder::new(TokioExecutor::new())
+            .handshake::<_, T>(io)
+            .await
+            .map_err(ConnectionManagerError::HyperError)?;
+
+        let addr = self.addr;
+
+        tokio::task::spawn(async move {
+            if let Err(e) = conn.await {
+                log::error!("Connection to target: {} failed, error: {}", addr, e);
+            }
+        });
+
+        *sender = Some(new_sender.clone());
+
+        Ok(new_sender)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bb8::Pool;
+    use http::Response;
+    use http_body_util::Full;
+    use hyper::body::Bytes;
+
+    use super::*;
+    use crate::{connection_pool::UpstreamBody, test_util::serve_http};
+
+    #[tokio::test]
+    async fn discarded_connection_is_not_reused() {
+        let addr = serve_http(|_| Response::new(Full::new(Bytes::new()))).await;
+        let pool = Pool::builder()
+            .max_size(1)
+            .build(ConnectionManager::<UpstreamBody>::new(addr, None))
+            .await
+            .unwrap();
+
+        drop(pool.get().await.unwrap());
+        assert_eq!(pool.state().statistics.connections_closed_broken, 0);
+
+        pool.get().await.unwrap().discard();
+        assert_eq!(pool.state().statistics.connections_closed_broken, 1);
+        assert_eq!(pool.state().statistics.connections_created, 1);
+
+        drop(pool.get().await.unwrap());
+        assert_eq!(pool.state().statistics.connections_created, 2);
     }
 }
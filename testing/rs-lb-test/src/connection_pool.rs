@@ -1,33 +1,45 @@
 use std::{
     collections::{HashMap, HashSet},
     net::{SocketAddr, ToSocketAddrs},
-    sync::{Arc, atomic::AtomicBool},
+    sync::Arc,
 };
 
-use bb8::Pool;
-use http_body_util::Empty;
-use hyper::body::{Body, Bytes};
+use bb8::{Pool, PooledConnection, RunError};
+use http::{Request, Response};
+use http_body_util::{Empty, combinators::BoxBody};
+use hyper::{
+    body::{Body, Bytes, Incoming},
+    client::conn::http2,
+};
 use tokio::sync::RwLock;
 
 use crate::{
-    connection_manager::{ConnectionManager, ConnectionManagerError},
-    connection_pool,
+    circuit_breaker::TargetCircuitStatus,
+    config::UpstreamProtocol,
+    connection_manager::{ConnectionManager, ConnectionManagerError, MultiplexedConnection},
+    outlier_detection::TargetOutlierStatus,
+    selector::TargetLoad,
     target::TargetGroup,
+    tls::{TargetTls, TlsError},
 };
 
+/// Body of requests proxied to targets, either the client's streamed body or a buffered copy
+/// which can be sent again on retries.
+pub type UpstreamBody = BoxBody<Bytes, hyper::Error>;
+
 #[derive(Debug, thiserror::Error)]
 pub enum ConnectionPoolCreationError {
     #[error("Failed to create connection pool for target group: {0}, due to error: {1}")]
     PoolCreation(String, ConnectionManagerError),
     #[error("Failed to get socket address for target group: {0}, due to error: {1}")]
     SocketAddressCreation(String, std::io::Error),
-    #[error("Failed to create health check pools: {0}")]
-    CreateHealthCheckPool(TargetConnectionPoolCloneError),
+    #[error("Invalid TLS configuration for target group: {0}, error: {1}")]
+    Tls(String, TlsError),
 }
 
 pub struct TargetGroupsConnectionPools<T>
 where
-    T: Send + Sync + Body + 'static,
+    T: Send + Sync + Body + Unpin + 'static,
     T::Data: Send,
     T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
 {
@@ -36,50 +48,10 @@ where
 
 impl<T> TargetGroupsConnectionPools<T>
 where
-    T: Send + Sync + Body + 'static,
+    T: Send + Sync + Body + Unpin + 'static,
     T::Data: Send,
     T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
 {
-    pub async fn create_health_check_pools(
-        &self,
-    ) -> Result<HashMap<String, Vec<TargetConnectionPool<Empty<Bytes>>>>, ConnectionPoolCreationError>
-    {
-        let mut groups_health_check_connection_pools = HashMap::new();
-
-        for (group_name, connection_pool) in self.groups_connection_pools.iter() {
-            let mut group_health_check_connection_pools = Vec::new();
-
-            let connection_pool_guard = connection_pool.read().await;
-
-            for pool in connection_pool_guard.iter() {
-                let health_check_pool = pool
-                    .create_health_check_pool()
-                    .await
-                    .map_err(ConnectionPoolCreationError::CreateHealthCheckPool)?;
-
-                group_health_check_connection_pools.push(health_check_pool);
-            }
-            groups_health_check_connection_pools
-                .insert(group_name.clone(), group_health_check_connection_pools);
-        }
-
-        Ok(groups_health_check_connection_pools)
-    }
-
-    // pub fn unwrap(self) -> HashMap<String, Vec<TargetConnectionPool<T>>> {
-    //     self.groups_connection_pools
-    //         .into_iter()
-    //         .map(|(k, v)| {
-    //             (
-    //                 k,
-    //                 Arc::into_inner(v)
-    //                     .map(RwLock::into_inner)
-    //                     .expect("Failed to unwrap locks"),
-    //             )
-    //         })
-    //         .collect()
-    // }
-
     pub fn get_pool_for_group(
         &self,
         target_group: &str,
@@ -103,28 +75,43 @@ where
                     (t.hostname.as_ref(), t.port)
                         .to_socket_addrs()
                         .map(|s| s.collect::<HashSet<SocketAddr>>())
-                        .map(|s| (s, t.uri.clone()))
+                        .map(|s| (s, t.uri.clone(), t.weight, t.hostname.as_str()))
                 })
                 .collect::<Result<Vec<_>, _>>()
                 .map_err(|e| {
                     ConnectionPoolCreationError::SocketAddressCreation(group_name.clone(), e)
                 })?
                 .into_iter()
-                .flat_map(|(s, u)| s.into_iter().map(move |s| (s, u.clone())));
+                .flat_map(|(s, u, w, h)| s.into_iter().map(move |s| (s, u.clone(), w, h)));
 
             let mut connections = Vec::new();
 
-            for (socket, uri) in socked_addrs {
+            for (socket, uri, weight, hostname) in socked_addrs {
+                let tls = target_group
+                    .tls
+                    .as_ref()
+                    .map(|tls| tls.for_target(hostname))
+                    .transpose()
+                    .map_err(|e| ConnectionPoolCreationError::Tls(group_name.clone(), e))?;
+
                 connections.push(TargetConnectionPool {
-                    connection_pool: Pool::builder()
-                        .max_size(pool_size)
-                        .build(ConnectionManager::new(socket))
-                        .await
-                        .map_err(|e| {
-                            ConnectionPoolCreationError::PoolCreation(group_name.clone(), e)
-                        })?,
+                    connection_pool: TargetConnections::new(
+                        socket,
+                        tls.clone(),
+                        target_group.protocol,
+                        target_group.max_connections.unwrap_or(pool_size),
+                    )
+                    .await
+                    .map_err(|e| {
+                        ConnectionPoolCreationError::PoolCreation(group_name.clone(), e)
+                    })?,
                     _socket_addr: socket,
+                    tls,
                     uri,
+                    weight,
+                    load: Arc::new(TargetLoad::new()),
+                    outlier_status: TargetOutlierStatus::new(),
+                    circuit_status: Arc::new(TargetCircuitStatus::new()),
                 });
             }
 
@@ -139,32 +126,50 @@ where
 
 pub struct TargetConnectionPool<T>
 where
-    T: Send + Sync + Body + 'static,
+    T: Send + Sync + Body + Unpin + 'static,
     T::Data: Send,
     T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
 {
-    pub connection_pool: Pool<ConnectionManager<T>>,
+    pub connection_pool: TargetConnections<T>,
     pub uri: String,
     pub _socket_addr: SocketAddr,
+    pub tls: Option<TargetTls>,
+    pub weight: usize,
+    pub load: Arc<TargetLoad>,
+    pub outlier_status: TargetOutlierStatus,
+    pub circuit_status: Arc<TargetCircuitStatus>,
 }
 
 impl<T> TargetConnectionPool<T>
 where
-    T: Send + Sync + Body + 'static,
+    T: Send + Sync + Body + Unpin + 'static,
     T::Data: Send,
     T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
 {
+    /// Scheme of requests to the target, which HTTP/2 requests carry in their uri.
+    pub fn scheme(&self) -> &'static str {
+        if self.tls.is_some() { "https" } else { "http" }
+    }
+
     pub async fn create_health_check_pool(
         &self,
     ) -> Result<TargetConnectionPool<Empty<Bytes>>, TargetConnectionPoolCloneError> {
         Ok(TargetConnectionPool::<Empty<Bytes>> {
-            connection_pool: Pool::builder()
-                .max_size(1)
-                .build(ConnectionManager::new(self._socket_addr))
-                .await
-                .map_err(TargetConnectionPoolCloneError::CreateNewPool)?,
+            connection_pool: TargetConnections::new(
+                self._socket_addr,
+                self.tls.clone(),
+                self.connection_pool.protocol(),
+                1,
+            )
+            .await
+            .map_err(TargetConnectionPoolCloneError::CreateNewPool)?,
             uri: self.uri.clone(),
             _socket_addr: self._socket_addr,
+            tls: self.tls.clone(),
+            weight: self.weight,
+            load: Arc::new(TargetLoad::new()),
+            outlier_status: TargetOutlierStatus::new(),
+            circuit_status: Arc::new(TargetCircuitStatus::new()),
         })
     }
 }
@@ -174,3 +179,112 @@ pub enum TargetConnectionPoolCloneError {
     #[error("Failed to create new pool, error: {0}")]
     CreateNewPool(ConnectionManagerError),
 }
+
+/// Connections to a single target, depending on the [`UpstreamProtocol`] of its group.
+pub enum TargetConnections<T>
+where
+    T: Send + Sync + Body + Unpin + 'static,
+    T::Data: Send,
+    T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
+{
+    Pooled(Pool<ConnectionManager<T>>),
+    Multiplexed(MultiplexedConnection<T>),
+}
+
+impl<T> TargetConnections<T>
+where
+    T: Send + Sync + Body + Unpin + 'static,
+    T::Data: Send,
+    T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
+{
+    pub async fn new(
+        addr: SocketAddr,
+        tls: Option<TargetTls>,
+        protocol: UpstreamProtocol,
+        pool_size: u32,
+    ) -> Result<Self, ConnectionManagerError> {
+        match protocol {
+            UpstreamProtocol::Http1 => Ok(Self::Pooled(
+                Pool::builder()
+                    .max_size(pool_size)
+                    .build(ConnectionManager::new(addr, tls))
+                    .await?,
+            )),
+            UpstreamProtocol::Http2 => Ok(Self::Multiplexed(MultiplexedConnection::new(addr, tls))),
+        }
+    }
+
+    pub fn protocol(&self) -> UpstreamProtocol {
+        match self {
+            Self::Pooled(_) => UpstreamProtocol::Http1,
+            Self::Multiplexed(_) => UpstreamProtocol::Http2,
+        }
+    }
+
+    pub async fn get(&self) -> Result<UpstreamConnection<T>, RunError<ConnectionManagerError>> {
+        match self {
+            Self::Pooled(pool) => {
+                // The pool retries failed connects until `get` times out, connecting here while
+                // no connection is idle reports a refused or failed connect right away. A full
+                // pool refuses the new connection, the request then waits for a pooled one.
+                let state = pool.state();
+
+                if state.idle_connections == 0 && state.connections < pool.config().max_size {
+                    let connection = pool.dedicated_connection().await.map_err(RunError::User)?;
+                    let _ = pool.add(connection);
+                }
+
+                pool.get_owned().await.map(UpstreamConnection::Pooled)
+            }
+            Self::Multiplexed(connection) => connection
+                .get()
+                .await
+                .map(UpstreamConnection::Multiplexed)
+                .map_err(RunError::User),
+        }
+    }
+}
+
+/// A pooled HTTP/1 connection checked out for one request, or a handle to a shared HTTP/2
+/// connection.
+pub enum UpstreamConnection<T>
+where
+    T: Send + Sync + Body + Unpin + 'static,
+    T::Data: Send,
+    T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
+{
+    Pooled(PooledConnection<'static, ConnectionManager<T>>),
+    Multiplexed(http2::SendRequest<T>),
+}
+
+impl<T> UpstreamConnection<T>
+where
+    T: Send + Sync + Body + Unpin + 'static,
+    T::Data: Send,
+    T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
+{
+    pub async fn ready(&mut self) -> Result<(), hyper::Error> {
+        match self {
+            Self::Pooled(connection) => connection.ready().await,
+            Self::Multiplexed(connection) => connection.ready().await,
+        }
+    }
+
+    pub async fn send_request(
+        &mut self,
+        request: Request<T>,
+    ) -> Result<Response<Incoming>, hyper::Error> {
+        match self {
+            Self::Pooled(connection) => connection.send_request(request).await,
+            Self::Multiplexed(connection) => connection.send_request(request).await,
+        }
+    }
+
+    /// Keeps a pooled connection from being reused once it is dropped. Multiplexed connections
+    /// only lose the stream of the abandoned request.
+    pub fn discard(&mut self) {
+        if let Self::Pooled(connection) = self {
+            connection.discard();
+        }
+    }
+}
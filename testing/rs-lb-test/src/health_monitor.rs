@@ -4,21 +4,19 @@ use std::{
     time::Duration,
 };
 
-use futures::{Stream, StreamExt, stream::FuturesUnordered};
+use futures::{StreamExt, future::join_all, stream::FuturesUnordered};
 use http::{Method, Request, StatusCode, Uri};
 use http_body_util::Empty;
-use hyper::body::{Body, Bytes, Incoming};
+use hyper::body::Bytes;
 use tokio::{
-    select,
     sync::RwLock,
     time::{Instant, sleep},
 };
 
 use crate::{
-    config::{TargetGroupConfiguration, TargetGroupHealthCheckConfiguration},
-    connection_pool::{
-        TargetConnectionPool, TargetConnectionPoolCloneError, TargetGroupsConnectionPools,
-    },
+    config::{TargetGroupConfiguration, TargetGroupHealthCheckConfiguration, UpstreamProtocol},
+    connection_manager::ConnectionManagerError,
+    connection_pool::{TargetConnectionPool, TargetConnectionPoolCloneError, UpstreamBody},
 };
 
 pub struct HealthMonitor {
@@ -27,7 +25,7 @@ pub struct HealthMonitor {
 
 impl HealthMonitor {
     pub async fn new(
-        connection_pools: HashMap<String, Arc<RwLock<Vec<TargetConnectionPool<Incoming>>>>>,
+        connection_pools: HashMap<String, Arc<RwLock<Vec<TargetConnectionPool<UpstreamBody>>>>>,
         target_group_configurations: &HashMap<String, TargetGroupConfiguration>,
     ) -> Result<Option<Self>, TargetGroupHealthCheckCreationError> {
         let mut health_check_targets = Vec::new();
@@ -71,6 +69,8 @@ impl HealthMonitor {
     }
 }
 
+/// Consecutive health check results for a target. Healthy targets count consecutive failures,
+/// unhealthy targets count consecutive successes.
 pub enum HealthCheckStats {
     SuccessfulCheckCount(usize),
     UnsuccessfulCheckCount(usize),
@@ -81,14 +81,16 @@ impl HealthCheckStats {
         HealthCheckStats::UnsuccessfulCheckCount(0)
     }
 
+    /// Returns whether the target is healthy, switching state once the relevant threshold of
+    /// consecutive results is reached.
     pub fn check_health(&mut self, failure_threshold: usize, success_threshold: usize) -> bool {
         match self {
-            Self::UnsuccessfulCheckCount(count) if *count > failure_threshold => {
+            Self::UnsuccessfulCheckCount(count) if *count >= failure_threshold => {
                 self.mark_unhealthy();
                 false
             }
             Self::UnsuccessfulCheckCount(_) => true,
-            Self::SuccessfulCheckCount(count) if *count > success_threshold => {
+            Self::SuccessfulCheckCount(count) if *count >= success_threshold => {
                 self.mark_healthy();
                 true
             }
@@ -97,117 +99,109 @@ impl HealthCheckStats {
     }
 
     pub fn mark_unhealthy(&mut self) {
-        std::mem::replace(self, Self::SuccessfulCheckCount(0));
+        *self = Self::SuccessfulCheckCount(0);
     }
 
     pub fn mark_healthy(&mut self) {
-        std::mem::replace(self, Self::UnsuccessfulCheckCount(0));
+        *self = Self::UnsuccessfulCheckCount(0);
     }
 
     pub fn register_health_check(&mut self, is_successful: bool) {
         match (is_successful, self) {
-            (true, Self::UnsuccessfulCheckCount(0)) => (),
-            (true, Self::UnsuccessfulCheckCount(count)) => *count -= 1,
+            (true, Self::UnsuccessfulCheckCount(count)) => *count = 0,
             (true, Self::SuccessfulCheckCount(count)) => *count += 1,
-            (false, Self::SuccessfulCheckCount(0)) => (),
-            (false, Self::SuccessfulCheckCount(count)) => *count -= 1,
+            (false, Self::SuccessfulCheckCount(count)) => *count = 0,
             (false, Self::UnsuccessfulCheckCount(count)) => *count += 1,
         }
     }
 }
 
-pub enum PoolPosition {
-    Healthy(usize),
-    Unhealthy(usize),
-}
-
-impl PoolPosition {
-    fn in_healthy_queue(&self) -> bool {
-        match self {
-            Self::Healthy(_) => true,
-            Self::Unhealthy(_) => false,
-        }
-    }
+#[derive(Debug, thiserror::Error)]
+pub enum HealthCheckError {
+    #[error("Failed to build request for health check: {0}")]
+    BuildRequest(http::Error),
+    #[error("Failed to get pooled connection for health check: {0}")]
+    GetConnection(bb8::RunError<ConnectionManagerError>),
+    #[error("Failed to get ready connection during health check: {0}")]
+    ReadyConnection(hyper::Error),
+    #[error("Failed to send request during health check: {0}")]
+    SendRequest(hyper::Error),
+    #[error("Health check failed with status: {0}")]
+    Status(StatusCode),
+    #[error("Health check request timeout")]
+    Timeout,
 }
 
 pub struct HealthCheckTarget {
     connection_pool: TargetConnectionPool<Empty<Bytes>>,
     health_check_stats: HealthCheckStats,
-    pool_position: PoolPosition,
     pub success_threshold: usize,
     pub failure_threshold: usize,
 }
 
 impl HealthCheckTarget {
-    fn update_pool_position(&mut self, pool_position: PoolPosition) -> PoolPosition {
-        std::mem::replace(&mut self.pool_position, pool_position)
-    }
-
     fn is_healthy(&mut self) -> bool {
         self.health_check_stats
             .check_health(self.failure_threshold, self.success_threshold)
     }
 
-    async fn run_check_health(&mut self, path: &str, timeout: Duration) {
-        let uri = match Uri::builder().path_and_query(path).build() {
-            Ok(u) => u,
-            Err(e) => {
-                log::error!("Failed to build uri for health check: {}", e);
+    async fn send_health_check(&self, path: &str) -> Result<(), HealthCheckError> {
+        let mut uri = Uri::builder().path_and_query(path);
 
-                self.health_check_stats.register_health_check(false);
-                return;
-            }
-        };
+        // HTTP/2 requests carry the scheme and authority in the uri instead of the Host header.
+        if self.connection_pool.connection_pool.protocol() == UpstreamProtocol::Http2 {
+            uri = uri
+                .scheme(self.connection_pool.scheme())
+                .authority(self.connection_pool._socket_addr.to_string());
+        }
 
-        let request = match Request::builder()
-            .uri(uri)
+        let request = Request::builder()
+            .uri(uri.build().map_err(HealthCheckError::BuildRequest)?)
             .method(Method::GET)
             .body(Empty::new())
+            .map_err(HealthCheckError::BuildRequest)?;
+
+        let mut target = self
+            .connection_pool
+            .connection_pool
+            .get()
+            .await
+            .map_err(HealthCheckError::GetConnection)?;
+
+        target
+            .ready()
+            .await
+            .map_err(HealthCheckError::ReadyConnection)?;
+
+        match target
+            .send_request(request)
+            .await
+            .map(|r| r.status())
+            .map_err(HealthCheckError::SendRequest)?
         {
-            Ok(r) => r,
-            Err(e) => {
-                log::error!("Failed to build request for health check: {}", e);
+            StatusCode::OK => Ok(()),
+            status => Err(HealthCheckError::Status(status)),
+        }
+    }
 
-                self.health_check_stats.register_health_check(false);
-                return;
-            }
-        };
+    async fn run_check_health(&mut self, path: &str, timeout: Duration) {
+        let result = tokio::time::timeout(timeout, self.send_health_check(path))
+            .await
+            .unwrap_or(Err(HealthCheckError::Timeout));
 
-        let mut target = match self.connection_pool.connection_pool.get().await {
-            Ok(t) => t,
-            Err(e) => {
-                log::error!("Failed to get pooled connection for health check: {}", e);
+        if let Err(e) = &result {
+            log::warn!("Health check for target: {} failed: {}", self.address(), e);
+        }
 
-                self.health_check_stats.register_health_check(false);
-                return;
-            }
-        };
+        self.health_check_stats
+            .register_health_check(result.is_ok());
+    }
 
-        if let Err(e) = target.ready().await {
-            log::error!("Failed to get ready connection during health check: {}", e);
-            self.health_check_stats.register_health_check(false);
-            return;
-        };
-
-        select! {
-          response_result = target.send_request(request) => {
-            match response_result.map(|r|r.status()) {
-                Ok(StatusCode::OK) => self.health_check_stats.register_health_check(true),
-                Ok(s) => {
-                  log::error!("Health check failed with status: {}", e);
-                  self.health_check_stats.register_health_check(false);
-                },
-                Err(e) => {
-                  log::error!("Failed to send request during health check: {}", e);
-                  self.health_check_stats.register_health_check(false);
-                }
-            }
-          },
-          _ = sleep(timeout) => {
-            log::error!("Health check request timeout");
-            self.health_check_stats.register_health_check(false);
-          }
-        }
+    fn address(&self) -> String {
+        format!(
+            "{}/{}",
+            self.connection_pool._socket_addr, self.connection_pool.uri
+        )
     }
 }
 
@@ -217,9 +211,15 @@ pub enum TargetGroupHealthCheckCreationError {
     CreateHealthCheckPool(TargetConnectionPoolCloneError),
 }
 
+/// Active health checks for the targets of one target group.
+///
+/// `healthy_health_check_connection_pool` is kept in the same order as the shared
+/// `source_connection_pool` used by the load balancer, and `unhealthy_health_check_connection_pool`
+/// in the same order as `unhealthy_connection_pool`, so a target can be moved between the two
+/// sides by index.
 pub struct TargetGroupHealthCheck {
-    pub source_connection_pool: Arc<RwLock<Vec<TargetConnectionPool<Incoming>>>>,
-    pub unhealthy_connection_pool: Vec<TargetConnectionPool<Incoming>>,
+    pub source_connection_pool: Arc<RwLock<Vec<TargetConnectionPool<UpstreamBody>>>>,
+    pub unhealthy_connection_pool: Vec<TargetConnectionPool<UpstreamBody>>,
     pub healthy_health_check_connection_pool: Vec<HealthCheckTarget>,
     pub unhealthy_health_check_connection_pool: Vec<HealthCheckTarget>,
     pub timeout: Duration,
@@ -229,14 +229,14 @@ pub struct TargetGroupHealthCheck {
 
 impl TargetGroupHealthCheck {
     pub async fn new(
-        connection_pool: Arc<RwLock<Vec<TargetConnectionPool<Incoming>>>>,
+        connection_pool: Arc<RwLock<Vec<TargetConnectionPool<UpstreamBody>>>>,
         health_check_configuration: &TargetGroupHealthCheckConfiguration,
     ) -> Result<Self, TargetGroupHealthCheckCreationError> {
         let mut health_check_connection_pool = Vec::new();
 
         let connection_pool_guard = connection_pool.read().await;
 
-        for (idx, pool) in connection_pool_guard.iter().enumerate() {
+        for pool in connection_pool_guard.iter() {
             let health_check_pool = pool
                 .create_health_check_pool()
                 .await
@@ -245,9 +245,8 @@ impl TargetGroupHealthCheck {
             health_check_connection_pool.push(HealthCheckTarget {
                 connection_pool: health_check_pool,
                 health_check_stats: HealthCheckStats::new_healthy(),
-                pool_position: PoolPosition::Healthy(idx),
-                failure_threshold: health_check_configuration.success_threshold,
-                success_threshold: health_check_configuration.failure_threshold,
+                failure_threshold: health_check_configuration.failure_threshold,
+                success_threshold: health_check_configuration.success_threshold,
             });
         }
 
@@ -268,10 +267,9 @@ impl TargetGroupHealthCheck {
         log::debug!("Running health check cycle");
         let health_check_start_time = Instant::now();
 
-        // self.check_healthy_connection_pools().await;
-        // self.check_unhealthy_connection_pools().await;
-        // self.filter_healthy_connection_pools().await;
-        // self.filter_unhealthy_connection_pools().await;
+        let recently_unhealthy = self.check_healthy_connection_pools().await;
+        self.check_unhealthy_connection_pools(recently_unhealthy)
+            .await;
 
         let health_check_duration = health_check_start_time.elapsed();
 
@@ -282,123 +280,267 @@ impl TargetGroupHealthCheck {
         self
     }
 
+    /// Checks every target currently serving traffic and evicts those which reached the failure
+    /// threshold. Returns the positions of the evicted targets in the unhealthy pools.
     pub async fn check_healthy_connection_pools(&mut self) -> HashSet<usize> {
-        let mut unhealthy_indexes = Vec::new();
-
-        for (idx, connection) in self
+        join_all(
+            self.healthy_health_check_connection_pool
+                .iter_mut()
+                .map(|connection| connection.run_check_health(&self.path, self.timeout)),
+        )
+        .await;
+
+        let mut unhealthy_indexes = self
             .healthy_health_check_connection_pool
             .iter_mut()
             .enumerate()
-        {
-            connection.run_check_health(&self.path, self.timeout).await;
+            .filter_map(|(idx, connection)| (!connection.is_healthy()).then_some(idx))
+            .collect::<Vec<_>>();
 
-            if !connection.is_healthy() {
-                unhealthy_indexes.push(idx);
-            }
+        if unhealthy_indexes.is_empty() {
+            return HashSet::new();
         }
 
+        // Remove from the back so earlier indexes stay valid.
         unhealthy_indexes.reverse();
 
         let mut source_connection_pool_guard = self.source_connection_pool.write().await;
+        let mut recently_unhealthy = HashSet::new();
 
-        let
+        for idx in unhealthy_indexes {
+            let health_check_target = self.healthy_health_check_connection_pool.remove(idx);
 
-        for idx in unhealthy_indexes.iter() {
-            let connection = source_connection_pool_guard.remove(*idx);
-            self.unhealthy_connection_pool.push(connection);
+            log::warn!(
+                "Removing unhealthy target: {} from target group",
+                health_check_target.address()
+            );
 
-            let health_check_target = self.healthy_health_check_connection_pool.remove(*idx);
+            recently_unhealthy.insert(self.unhealthy_connection_pool.len());
+
+            self.unhealthy_connection_pool
+                .push(source_connection_pool_guard.remove(idx));
             self.unhealthy_health_check_connection_pool
                 .push(health_check_target);
         }
 
-        unhealthy_indexes.into_iter().collect()
+        recently_unhealthy
     }
 
+    /// Checks every evicted target, except those evicted during this cycle, and restores those
+    /// which reached the success threshold.
     pub async fn check_unhealthy_connection_pools(&mut self, recently_unhealthy: HashSet<usize>) {
-        let mut healthy_indexes = Vec::new();
-
-        for (idx, connection) in self
+        join_all(
+            self.unhealthy_health_check_connection_pool
+                .iter_mut()
+                .enumerate()
+                .filter(|(idx, _)| !recently_unhealthy.contains(idx))
+                .map(|(_, connection)| connection.run_check_health(&self.path, self.timeout)),
+        )
+        .await;
+
+        let mut healthy_indexes = self
             .unhealthy_health_check_connection_pool
             .iter_mut()
             .enumerate()
-        {
-            if
+            .filter(|(idx, _)| !recently_unhealthy.contains(idx))
+            .filter_map(|(idx, connection)| connection.is_healthy().then_some(idx))
+            .collect::<Vec<_>>();
 
-            connection.run_check_health(&self.path, self.timeout).await;
-
-            if !connection.is_healthy() {
-                healthy_indexes.push(idx);
-            }
+        if healthy_indexes.is_empty() {
+            return;
         }
 
         healthy_indexes.reverse();
 
         let mut source_connection_pool_guard = self.source_connection_pool.write().await;
 
-        for idx in healthy_indexes.iter() {
-            let connection = source_connection_pool_guard.remove(*idx);
-            self.unhealthy_connection_pool.push(connection);
+        for idx in healthy_indexes {
+            let health_check_target = self.unhealthy_health_check_connection_pool.remove(idx);
 
-            let health_check_target = self.healthy_health_check_connection_pool.remove(*idx);
-            self.unhealthy_health_check_connection_pool
+            log::info!(
+                "Restoring healthy target: {} to target group",
+                health_check_target.address()
+            );
+
+            source_connection_pool_guard.push(self.unhealthy_connection_pool.remove(idx));
+            self.healthy_health_check_connection_pool
                 .push(health_check_target);
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use http::Response;
+    use http_body_util::Full;
+
+    use super::*;
+    use crate::{
+        connection_pool::TargetGroupsConnectionPools,
+        target::TargetGroup,
+        test_util::{self, serve_http, target_group_pools},
+    };
+
+    /// Target whose health check passes while `healthy` is set.
+    async fn target_group(
+        healthy: Arc<AtomicBool>,
+        failure_threshold: usize,
+        success_threshold: usize,
+    ) -> TargetGroupHealthCheck {
+        let addr = serve_http(move |_| {
+            let status = match healthy.load(Ordering::SeqCst) {
+                true => StatusCode::OK,
+                false => StatusCode::SERVICE_UNAVAILABLE,
+            };
+
+            Response::builder()
+                .status(status)
+                .body(Full::default())
+                .unwrap()
+        })
+        .await;
+
+        TargetGroupHealthCheck::new(
+            target_group_pools(&[addr]).await,
+            &TargetGroupHealthCheckConfiguration {
+                path: "/health".to_owned(),
+                enabled: true,
+                interval: 0,
+                failure_threshold,
+                success_threshold,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Runs `cycles` health check cycles and returns the number of targets serving traffic.
+    async fn run_cycles(check: &mut Option<TargetGroupHealthCheck>, cycles: usize) -> usize {
+        for _ in 0..cycles {
+            *check = Some(check.take().unwrap().run_health_check_cycle().await);
+        }
+
+        check
+            .as_ref()
+            .unwrap()
+            .source_connection_pool
+            .read()
+            .await
+            .len()
+    }
+
+    #[tokio::test]
+    async fn evicts_after_failure_threshold_and_restores_after_success_threshold() {
+        let healthy = Arc::new(AtomicBool::new(true));
+        let mut check = Some(target_group(healthy.clone(), 2, 3).await);
+
+        assert_eq!(run_cycles(&mut check, 3).await, 1);
+
+        healthy.store(false, Ordering::SeqCst);
+        assert_eq!(run_cycles(&mut check, 1).await, 1);
+        assert_eq!(run_cycles(&mut check, 1).await, 0);
+
+        healthy.store(true, Ordering::SeqCst);
+        assert_eq!(run_cycles(&mut check, 2).await, 0);
+        assert_eq!(run_cycles(&mut check, 1).await, 1);
+    }
+
+    #[tokio::test]
+    async fn restores_only_after_consecutive_passes() {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let mut check = Some(target_group(healthy.clone(), 1, 3).await);
+
+        assert_eq!(run_cycles(&mut check, 1).await, 0);
+
+        healthy.store(true, Ordering::SeqCst);
+        assert_eq!(run_cycles(&mut check, 2).await, 0);
+
+        healthy.store(false, Ordering::SeqCst);
+        assert_eq!(run_cycles(&mut check, 1).await, 0);
 
-    // pub async fn check_unhealthy_connection_pools(&mut self) {
-    //     assert!(self.unhealthy_connection_pool.len() == self.unhealthy_stats.len());
-
-    //     for (idx, connection) in self.unhealthy_connection_pool.iter().enumerate() {
-    //         if check_health(connection, &self.path, self.timeout).await {
-    //             self.unhealthy_stats[idx] += 1;
-    //         } else {
-    //             self.unhealthy_stats[idx] == 0;
-    //         }
-    //     }
-    // }
-
-    // pub async fn filter_healthy_connection_pools(&mut self) {
-    //     let mut failed_indexes = self
-    //         .healthy_stats
-    //         .iter()
-    //         .filter(|c| *c >= &self.failure_threshold)
-    //         .enumerate()
-    //         .map(|(i, _)| i)
-    //         .collect::<Vec<_>>();
-
-    //     failed_indexes.reverse();
-
-    //     assert!(self.health_check_connection_pool.len() == self.healthy_stats.len());
-
-    //     for idx in failed_indexes {
-    //         let pool = self.health_check_connection_pool.remove(idx);
-    //         self.healthy_stats.remove(idx);
-
-    //         self.unhealthy_connection_pool.push(pool);
-    //         self.unhealthy_stats.push(0);
-    //     }
-    // }
-
-    // pub async fn filter_unhealthy_connection_pools(&mut self) {
-    //     let mut succeeded_indexes = self
-    //         .unhealthy_stats
-    //         .iter()
-    //         .filter(|c| *c >= &self.success_threshold)
-    //         .enumerate()
-    //         .map(|(i, _)| i)
-    //         .collect::<Vec<_>>();
-
-    //     succeeded_indexes.reverse();
-
-    //     assert!(self.unhealthy_connection_pool.len() == self.unhealthy_stats.len());
-
-    //     for idx in succeeded_indexes {
-    //         let pool = self.unhealthy_connection_pool.remove(idx);
-    //         self.unhealthy_stats.remove(idx);
-
-    //         self.health_check_connection_pool.push(pool);
-    //         self.healthy_stats.push(0);
-    //     }
-    // }
+        healthy.store(true, Ordering::SeqCst);
+        assert_eq!(run_cycles(&mut check, 2).await, 0);
+        assert_eq!(run_cycles(&mut check, 1).await, 1);
+    }
+
+    /// Failure and success thresholds used to be applied the wrong way round.
+    #[tokio::test]
+    async fn thresholds_are_not_swapped() {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let mut check = Some(target_group(healthy.clone(), 1, 4).await);
+
+        assert_eq!(run_cycles(&mut check, 1).await, 0);
+
+        healthy.store(true, Ordering::SeqCst);
+        assert_eq!(run_cycles(&mut check, 1).await, 0);
+        assert_eq!(run_cycles(&mut check, 2).await, 0);
+        assert_eq!(run_cycles(&mut check, 1).await, 1);
+    }
+
+    #[test]
+    fn stats_switch_state_at_thresholds() {
+        let mut stats = HealthCheckStats::new_healthy();
+
+        stats.register_health_check(false);
+        assert!(stats.check_health(2, 3));
+        stats.register_health_check(false);
+        assert!(!stats.check_health(2, 3));
+
+        for _ in 0..2 {
+            stats.register_health_check(true);
+            assert!(!stats.check_health(2, 3));
+        }
+
+        stats.register_health_check(true);
+        assert!(stats.check_health(2, 3));
+    }
+
+    #[tokio::test]
+    async fn http2_health_check_carries_scheme_and_authority() {
+        let addr = serve_http(|request| {
+            let status = match (request.uri().scheme_str(), request.uri().authority()) {
+                (Some("http"), Some(_)) => StatusCode::OK,
+                _ => StatusCode::BAD_REQUEST,
+            };
+
+            Response::builder()
+                .status(status)
+                .body(Full::default())
+                .unwrap()
+        })
+        .await;
+
+        let connection_pools = TargetGroupsConnectionPools::try_from_target_groups(
+            &HashMap::from([(
+                "group".to_owned(),
+                TargetGroup {
+                    protocol: UpstreamProtocol::Http2,
+                    ..test_util::target_group(&[addr])
+                },
+            )]),
+            4,
+        )
+        .await
+        .unwrap();
+
+        let mut check = Some(
+            TargetGroupHealthCheck::new(
+                connection_pools.get_pool_for_group("group").unwrap(),
+                &TargetGroupHealthCheckConfiguration {
+                    path: "/health".to_owned(),
+                    enabled: true,
+                    interval: 0,
+                    failure_threshold: 1,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap(),
+        );
+
+        assert_eq!(run_cycles(&mut check, 3).await, 1);
+    }
 }
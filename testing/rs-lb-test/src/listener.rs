@@ -1,9 +1,10 @@
-use crate::config::ListenerRuleConfiguration;
+use crate::config::{ListenerRuleConfiguration, ListenerRuleRetryConfiguration};
 
 pub struct ListenerRule {
     pub target_group: String,
     pub path_prefix: String,
     pub path_rewrite: String,
+    pub retry: ListenerRuleRetryConfiguration,
 }
 
 impl From<ListenerRuleConfiguration> for ListenerRule {
@@ -12,6 +13,7 @@ impl From<ListenerRuleConfiguration> for ListenerRule {
             target_group,
             path_prefix: raw_prefix,
             path_rewrite: raw_rewrite,
+            retry,
         }: ListenerRuleConfiguration,
     ) -> Self {
         let path_prefix = format!(
@@ -27,6 +29,7 @@ impl From<ListenerRuleConfiguration> for ListenerRule {
             target_group,
             path_prefix,
             path_rewrite,
+            retry,
         }
     }
 }
@@ -1,34 +1,60 @@
-use std::{collections::HashMap, convert::Infallible, sync::Arc, time::Duration};
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
 
-use http::StatusCode;
-use http::{Request, Response, Uri, uri::PathAndQuery};
-use http_body_util::{BodyExt, Full};
+use bb8::RunError;
+use http::header::{HOST, SET_COOKIE};
+use http::{HeaderValue, Method, Version};
+use http::{Request, Response, Uri, request::Parts, uri::PathAndQuery};
+use http_body_util::BodyExt;
 use hyper::body::Incoming;
-use hyper::{body::Bytes, server::conn::http1, service::service_fn};
-use hyper_util::rt::TokioIo;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
 use log::{debug, error};
 use tokio::sync::RwLock;
-use tokio::{net::TcpStream, select, time::sleep};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::{Instant, timeout},
+};
 
 use crate::cache::RequestCache;
 use crate::{
-    connection_pool::{TargetConnectionPool, TargetGroupsConnectionPools},
+    body::{ResponseBody, StreamingBody, full},
+    circuit_breaker::CircuitBreaker,
+    config::{
+        LoadBalancingAlgorithm, SessionAffinityMode, TargetGroupConfiguration, UpstreamProtocol,
+    },
+    connection_pool::{TargetConnectionPool, TargetGroupsConnectionPools, UpstreamBody},
     listener::ListenerRule,
-    selector::RoundRobin,
+    outlier_detection::OutlierDetector,
+    proxy_error::{ProxyError, ProxyErrorCounters},
+    retry::{RetryPolicy, buffer_body, is_retryable_status},
+    selector::{Selector, new_selector},
+    session_affinity::{SessionAffinity, SessionAffinityError},
 };
 
+#[derive(Debug, thiserror::Error)]
+pub enum LoadBalancerCreationError {
+    #[error("Listener rule references unknown target group: {0}")]
+    MissingTargetGroup(String),
+    #[error("Invalid session affinity for target group: {0}, error: {1}")]
+    SessionAffinity(String, SessionAffinityError),
+}
+
 pub struct LoadBalancer {
     pub listener_targets: HashMap<String, ListenerRuleHandler>,
     pub prefixes: Vec<String>,
     pub cache: Option<Arc<RequestCache>>,
+    pub error_counters: ProxyErrorCounters,
 }
 
 impl LoadBalancer {
     pub async fn new(
         listener_rules: Vec<ListenerRule>,
-        connection_pools: &TargetGroupsConnectionPools<Incoming>,
+        connection_pools: &TargetGroupsConnectionPools<UpstreamBody>,
+        target_group_configurations: &HashMap<String, TargetGroupConfiguration>,
+        load_balancing_algorithm: LoadBalancingAlgorithm,
         connection_timeout: Duration,
-    ) -> Self {
+    ) -> Result<Self, LoadBalancerCreationError> {
         let mut prefixes: Vec<String> = listener_rules
             .iter()
             .map(|r| format!("{}/", r.path_prefix.trim_end_matches("/")))
@@ -37,47 +63,92 @@ impl LoadBalancer {
         prefixes.sort();
         prefixes.reverse();
 
-        let listener_targets = listener_rules
-            .into_iter()
-            .map(|r| {
-                (
-                    format!("{}/", r.path_prefix.trim_end_matches("/")),
-                    ListenerRuleHandler {
-                        selector: RoundRobin::new(),
-                        connection_pool: connection_pools
-                            .get_pool_for_group(&r.target_group)
-                            .expect("Missing target group"),
-                        path_rewrite: r.path_rewrite,
-                        connection_timeout,
-                    },
-                )
-            })
-            .collect();
+        let mut listener_targets = HashMap::new();
 
-        Self {
+        for r in listener_rules {
+            let target_group_configuration = target_group_configurations.get(&r.target_group);
+
+            let connection_pool = connection_pools
+                .get_pool_for_group(&r.target_group)
+                .ok_or_else(|| {
+                    LoadBalancerCreationError::MissingTargetGroup(r.target_group.clone())
+                })?;
+
+            let algorithm = target_group_configuration
+                .and_then(|c| c.load_balancing_algorithm)
+                .unwrap_or(load_balancing_algorithm);
+
+            let mut session_affinity_configuration = target_group_configuration
+                .map(|c| c.session_affinity.clone())
+                .unwrap_or_default();
+
+            if let LoadBalancingAlgorithm::Sticky = algorithm
+                && session_affinity_configuration.mode == SessionAffinityMode::None
+            {
+                session_affinity_configuration.mode = SessionAffinityMode::Cookie;
+            }
+
+            let session_affinity = SessionAffinity::new(
+                &session_affinity_configuration,
+                &connection_pool.read().await,
+            )
+            .map_err(|e| LoadBalancerCreationError::SessionAffinity(r.target_group.clone(), e))?;
+
+            listener_targets.insert(
+                format!("{}/", r.path_prefix.trim_end_matches("/")),
+                ListenerRuleHandler {
+                    selector: new_selector(algorithm),
+                    connection_pool,
+                    outlier_detector: target_group_configuration
+                        .map(|c| &c.outlier_detection)
+                        .filter(|c| c.enabled)
+                        .map(OutlierDetector::from),
+                    session_affinity,
+                    circuit_breaker: target_group_configuration
+                        .map(|c| &c.circuit_breaker)
+                        .filter(|c| c.enabled)
+                        .map(CircuitBreaker::from),
+                    retry_policy: RetryPolicy::from(&r.retry),
+                    path_rewrite: r.path_rewrite,
+                    connection_timeout,
+                },
+            );
+        }
+
+        Ok(Self {
             listener_targets,
             prefixes,
             cache: Option::None,
-        }
+            error_counters: ProxyErrorCounters::default(),
+        })
     }
 
-    pub fn with_cache(self, ttl: Duration) -> Self {
-        let Self {
-            listener_targets,
-            prefixes,
-            ..
-        } = self;
+    pub fn with_cache(self, ttl: Duration, max_body_size: usize) -> Self {
         Self {
-            listener_targets,
-            prefixes,
-            cache: Some(RequestCache::new(ttl)),
+            cache: Some(RequestCache::new(ttl, max_body_size)),
+            ..self
         }
     }
 
-    pub async fn serve_connection(self: Arc<Self>, conn: TokioIo<TcpStream>) {
-        if let Err(err) = http1::Builder::new()
-            .keep_alive(true)
-            .serve_connection(conn, service_fn(|request| self.handle_connection(request)))
+    /// Serves a plain or TLS client connection.
+    pub async fn serve_connection<I>(self: Arc<Self>, conn: TokioIo<I>, client_addr: SocketAddr)
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder.http1().keep_alive(true);
+
+        // HTTP/2 is detected from the connection preface, sent by clients which negotiated it
+        // through ALPN or have prior knowledge (h2c).
+        if let Err(err) = builder
+            .serve_connection(
+                conn,
+                service_fn(|request| {
+                    let balancer = self.clone();
+
+                    async move { balancer.handle_connection(request, client_addr).await }
+                }),
+            )
             .await
         {
             error!("Error serving connection: {:?}", err);
@@ -94,31 +165,62 @@ impl LoadBalancer {
     pub async fn handle_connection(
         &self,
         request: Request<Incoming>,
-    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        client_addr: SocketAddr,
+    ) -> Result<Response<ResponseBody>, Infallible> {
         debug!("Handling Connection: {:?}", request);
 
-        let uri = request.uri().to_string();
+        // Only GET requests are served from and stored in the cache.
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|_| request.method() == Method::GET)
+            .map(|c| (c, request.uri().to_string()));
 
-        if let Some(response) = self.cache.as_ref().and_then(|c| c.get(&uri)) {
-            return Ok(response);
+        if let Some((cache, uri)) = &cache
+            && let Some(response) = cache.get(uri)
+        {
+            return Ok(response.map(|body| body.map_err(|never| match never {}).boxed()));
         }
 
-        let response = match self.match_uri(request.uri().path()) {
-            None => Response::builder()
-                .status(http::StatusCode::NOT_FOUND)
-                .body(Full::new(Bytes::new()))
-                .unwrap(),
-            Some(prefix) => {
-                self.listener_targets
-                    .get(prefix)
-                    .expect("Failed to get listener target")
-                    .handle_connection(request)
-                    .await?
+        let method = request.method().clone();
+        let path = request.uri().path().to_owned();
+
+        let result = match self
+            .match_uri(&path)
+            .and_then(|prefix| self.listener_targets.get(prefix))
+        {
+            None => Err(ProxyError::NoMatchingRule),
+            Some(handler) => handler.handle_connection(request, client_addr).await,
+        };
+
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                // Counted outside of the log statement, whose arguments are skipped when warnings
+                // are not logged.
+                let count = self.error_counters.increment(&e);
+
+                log::warn!(
+                    "Proxy error kind={} status={} method={} path={} client={} count={} error=\"{}\"",
+                    e.kind(),
+                    e.status().as_u16(),
+                    method,
+                    path,
+                    client_addr,
+                    count,
+                    e
+                );
+
+                return Ok(e.to_response());
             }
         };
 
-        if let Some(cache) = &self.cache {
-            cache.set(&uri, &response);
+        // Session cookies are specific to one client and must not be replayed to others.
+        if let Some((cache, uri)) = &cache
+            && response.status().is_success()
+            && !response.headers().contains_key(SET_COOKIE)
+        {
+            return Ok(cache.tee(uri, response));
         }
 
         Ok(response)
@@ -126,114 +228,722 @@ impl LoadBalancer {
 }
 
 pub struct ListenerRuleHandler {
-    pub selector: RoundRobin,
-    pub connection_pool: Arc<RwLock<Vec<TargetConnectionPool<Incoming>>>>,
+    pub selector: Box<dyn Selector>,
+    pub connection_pool: Arc<RwLock<Vec<TargetConnectionPool<UpstreamBody>>>>,
+    pub outlier_detector: Option<OutlierDetector>,
+    pub session_affinity: Option<SessionAffinity>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    pub retry_policy: RetryPolicy,
     pub path_rewrite: String,
     pub connection_timeout: Duration,
 }
 
+fn upstream_version(version: Version, target_pool: &TargetConnectionPool<UpstreamBody>) -> Version {
+    match target_pool.connection_pool.protocol() {
+        UpstreamProtocol::Http1 if version == Version::HTTP_2 => Version::HTTP_11,
+        UpstreamProtocol::Http1 => version,
+        UpstreamProtocol::Http2 => Version::HTTP_2,
+    }
+}
+
+fn is_retryable(outcome: &Result<Response<ResponseBody>, ProxyError>) -> bool {
+    match outcome {
+        Ok(response) => is_retryable_status(response.status()),
+        Err(e) => e.is_retryable(),
+    }
+}
+
 impl ListenerRuleHandler {
-    pub async fn handle_connection(
+    fn record_outcome(
         &self,
-        request: Request<Incoming>,
-    ) -> Result<Response<Full<Bytes>>, Infallible> {
-        let connection_pool_guard = self.connection_pool.read().await;
+        connection_pools: &[TargetConnectionPool<UpstreamBody>],
+        selection: usize,
+        is_error: bool,
+    ) {
+        if let Some(detector) = &self.outlier_detector {
+            detector.record(connection_pools, selection, is_error);
+        }
 
-        if connection_pool_guard.is_empty() {
-            return Ok(Response::builder()
-                .status(StatusCode::SERVICE_UNAVAILABLE)
-                .body(Full::new(Bytes::new()))
-                .unwrap());
+        if let Some(breaker) = &self.circuit_breaker
+            && let Some(target) = connection_pools.get(selection)
+        {
+            breaker.record(target, is_error);
         }
+    }
 
-        let selection = self.selector.next_wrapping(connection_pool_guard.len());
+    fn select(&self, connection_pools: &[TargetConnectionPool<UpstreamBody>]) -> usize {
+        let selection = self.selector.select(connection_pools);
 
-        let (mut target, uri) = match connection_pool_guard.get(selection) {
-            None => panic!("Cannot find connection"),
-            Some(c) => match c.connection_pool.get().await {
-                Ok(p) => (p, c.uri.clone()),
-                Err(e) => {
-                    log::error!("Failed to get pooled connection: {}", e);
+        match &self.outlier_detector {
+            Some(detector) => detector.select(connection_pools, selection),
+            None => selection,
+        }
+    }
 
-                    return Ok(Response::builder()
-                        .status(http::StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Full::new(Bytes::new()))
-                        .unwrap());
-                }
-            },
-        };
+    /// Picks a target for a retry which was not tried yet and is not ejected.
+    fn select_untried(
+        &self,
+        connection_pools: &[TargetConnectionPool<UpstreamBody>],
+        tried: &[usize],
+    ) -> Option<usize> {
+        let selection = self.selector.select(connection_pools);
+
+        (0..connection_pools.len())
+            .map(|offset| (selection + offset) % connection_pools.len())
+            .find(|idx| !tried.contains(idx) && !connection_pools[*idx].outlier_status.is_ejected())
+    }
 
-        let path_and_query = request.uri().path_and_query().unwrap();
+    fn upstream_uri(
+        &self,
+        parts: &Parts,
+        target_pool: &TargetConnectionPool<UpstreamBody>,
+    ) -> Result<Uri, ProxyError> {
+        let target_uri = &target_pool.uri;
+        let path_and_query = parts.uri.path_and_query().ok_or(ProxyError::MissingPath)?;
 
+        // A rewrite which does not match the path leaves it as is.
         let sanitised_path_and_query = path_and_query
             .path()
             .strip_prefix(&self.path_rewrite)
-            .expect("Failed to strip prefix for matched path. This should not happen.")
+            .unwrap_or(path_and_query.path())
             .trim_start_matches("/");
 
-        let rewritten_path = if uri.is_empty() {
+        let rewritten_path = if target_uri.is_empty() {
             format!("/{}", sanitised_path_and_query)
         } else {
-            format!("/{}/{}", uri, sanitised_path_and_query)
+            format!("/{}/{}", target_uri, sanitised_path_and_query)
         };
 
         let rewritten_path_and_query = match path_and_query.query() {
-            None => PathAndQuery::try_from(rewritten_path).unwrap(),
-            Some(query) => PathAndQuery::try_from(format!("{}?{}", rewritten_path, query)).unwrap(),
-        };
+            None => PathAndQuery::try_from(rewritten_path),
+            Some(query) => PathAndQuery::try_from(format!("{}?{}", rewritten_path, query)),
+        }
+        .map_err(ProxyError::InvalidUri)?;
 
         let mut uri_builder = Uri::builder().path_and_query(rewritten_path_and_query);
 
-        if let Some(authority) = request.uri().authority() {
+        if target_pool.connection_pool.protocol() == UpstreamProtocol::Http2 {
+            // HTTP/2 requests carry the authority in the uri instead of the Host header.
+            let authority = match parts.uri.authority() {
+                Some(authority) => authority.to_string(),
+                None => parts
+                    .headers
+                    .get(HOST)
+                    .and_then(|h| h.to_str().ok())
+                    .map(str::to_owned)
+                    .unwrap_or(target_pool._socket_addr.to_string()),
+            };
+
+            // The scheme follows the connection to the target, not the one of the client.
+            return uri_builder
+                .scheme(target_pool.scheme())
+                .authority(authority)
+                .build()
+                .map_err(ProxyError::BuildRequest);
+        }
+
+        if let Some(authority) = parts.uri.authority() {
             uri_builder = uri_builder.authority(authority.as_str());
         }
 
-        if let Some(scheme) = request.uri().scheme() {
+        if let Some(scheme) = parts.uri.scheme() {
             uri_builder = uri_builder.scheme(scheme.as_str());
         }
 
-        let uri = uri_builder.build().expect("Failed to build uri");
+        uri_builder.build().map_err(ProxyError::BuildRequest)
+    }
+
+    async fn attempt(
+        &self,
+        connection_pools: &[TargetConnectionPool<UpstreamBody>],
+        selection: usize,
+        parts: &Parts,
+        body: UpstreamBody,
+    ) -> Result<Response<ResponseBody>, ProxyError> {
+        let target_pool = &connection_pools[selection];
+        let socket_addr = target_pool._socket_addr;
+        let outstanding = target_pool.load.start();
+
+        let permit = match &self.circuit_breaker {
+            Some(breaker) => match breaker.try_acquire(target_pool) {
+                Some(permit) => Some(permit),
+                None => return Err(ProxyError::CircuitOpen(socket_addr)),
+            },
+            None => None,
+        };
+
+        let pending = match &self.circuit_breaker {
+            Some(breaker) => match breaker.start_pending(target_pool) {
+                Some(pending) => Some(pending),
+                None => return Err(ProxyError::TooManyPending(socket_addr)),
+            },
+            None => None,
+        };
+
+        let target = target_pool.connection_pool.get().await;
+
+        drop(pending);
+
+        let mut target = target.map_err(|e| {
+            // A pool timeout means every connection is in use, not that the target failed.
+            if let RunError::User(_) = e {
+                self.record_outcome(connection_pools, selection, true);
+            }
+
+            ProxyError::Connect(socket_addr, e)
+        })?;
 
-        let client_request = request
-            .headers()
+        let mut client_request = parts
+            .headers
             .iter()
             .fold(
                 Request::builder()
-                    .uri(uri)
-                    .method(request.method())
-                    .version(request.version()),
+                    .uri(self.upstream_uri(parts, target_pool)?)
+                    .method(&parts.method)
+                    .version(upstream_version(parts.version, target_pool)),
                 |b, (k, v)| b.header(k, v),
             )
-            .body(request.into_body())
-            .unwrap();
+            .body(body)
+            .map_err(ProxyError::BuildRequest)?;
+
+        // Requests from HTTP/2 clients have no Host header, which HTTP/1 targets expect.
+        if let Some(authority) = parts.uri.authority()
+            && !client_request.headers().contains_key(HOST)
+            && target_pool.connection_pool.protocol() == UpstreamProtocol::Http1
+            && let Ok(host) = HeaderValue::from_str(authority.as_str())
+        {
+            client_request.headers_mut().insert(HOST, host);
+        }
+
+        target.ready().await.map_err(|e| {
+            self.record_outcome(connection_pools, selection, true);
 
-        target
-            .ready()
+            ProxyError::ConnectionClosed(socket_addr, e)
+        })?;
+
+        let response = target.send_request(client_request).await.map_err(|e| {
+            self.record_outcome(connection_pools, selection, true);
+
+            ProxyError::SendRequest(socket_addr, e)
+        })?;
+
+        self.record_outcome(
+            connection_pools,
+            selection,
+            response.status().is_server_error(),
+        );
+
+        // The connection stays checked out and the request outstanding until the body is done.
+        Ok(response.map(|incoming_body| {
+            StreamingBody::new(incoming_body, move |completed| {
+                if !completed {
+                    target.discard();
+                }
+
+                drop(target);
+                drop(permit);
+
+                if completed {
+                    outstanding.finish();
+                }
+            })
+            .boxed()
+        }))
+    }
+
+    async fn timed_attempt(
+        &self,
+        connection_pools: &[TargetConnectionPool<UpstreamBody>],
+        selection: usize,
+        parts: &Parts,
+        body: UpstreamBody,
+        deadline: Instant,
+    ) -> Result<Response<ResponseBody>, ProxyError> {
+        let attempt_timeout = self
+            .retry_policy
+            .per_try_timeout()
+            .unwrap_or(self.connection_timeout)
+            .min(deadline.saturating_duration_since(Instant::now()));
+
+        match timeout(
+            attempt_timeout,
+            self.attempt(connection_pools, selection, parts, body),
+        )
+        .await
+        {
+            // Only failures of the target count against its response time, not requests which
+            // were rejected before reaching it.
+            Ok(Err(
+                e @ (ProxyError::Connect(_, RunError::User(_))
+                | ProxyError::ConnectionClosed(..)
+                | ProxyError::SendRequest(..)),
+            )) => {
+                connection_pools[selection]
+                    .load
+                    .record_response_time(attempt_timeout);
+
+                Err(e)
+            }
+            Ok(outcome) => outcome,
+            Err(_) => {
+                connection_pools[selection]
+                    .load
+                    .record_response_time(attempt_timeout);
+                self.record_outcome(connection_pools, selection, true);
+
+                Err(ProxyError::Timeout(
+                    connection_pools[selection]._socket_addr,
+                ))
+            }
+        }
+    }
+
+    pub async fn handle_connection(
+        &self,
+        request: Request<Incoming>,
+        client_addr: SocketAddr,
+    ) -> Result<Response<ResponseBody>, ProxyError> {
+        let _active = self.retry_policy.start_request();
+        let deadline = Instant::now() + self.connection_timeout;
+
+        let connection_pool_guard = self.connection_pool.read().await;
+
+        if connection_pool_guard.is_empty() {
+            return Err(ProxyError::NoTargets);
+        }
+
+        let (parts, body) = request.into_parts();
+
+        let (mut upstream_body, retry_body) = if self.retry_policy.allows(&parts.method, &body) {
+            let bytes = buffer_body(body).await.map_err(ProxyError::RequestBody)?;
+
+            (full(bytes.clone()), Some(bytes))
+        } else {
+            (body.boxed(), None)
+        };
+
+        let affinity = self
+            .session_affinity
+            .as_ref()
+            .and_then(|a| a.select(&parts.headers, &client_addr, &connection_pool_guard));
+
+        let mut selection = affinity.unwrap_or_else(|| self.select(&connection_pool_guard));
+        let mut tried = vec![selection];
+        let mut _retry = None;
+
+        let outcome = loop {
+            let outcome = self
+                .timed_attempt(
+                    &connection_pool_guard,
+                    selection,
+                    &parts,
+                    upstream_body,
+                    deadline,
+                )
+                .await;
+
+            if !is_retryable(&outcome)
+                || tried.len() >= self.retry_policy.attempts()
+                || Instant::now() >= deadline
+            {
+                break outcome;
+            }
+
+            let Some(bytes) = &retry_body else {
+                break outcome;
+            };
+
+            let Some(next_selection) = self.select_untried(&connection_pool_guard, &tried) else {
+                break outcome;
+            };
+
+            let Some(retry) = self.retry_policy.start_retry() else {
+                debug!("Retry budget exhausted, not retrying request");
+
+                break outcome;
+            };
+
+            match &outcome {
+                Ok(response) => debug!(
+                    "Retrying request on target: {}, attempt: {}, previous status: {}",
+                    connection_pool_guard[next_selection]._socket_addr,
+                    tried.len() + 1,
+                    response.status()
+                ),
+                Err(e) => log::warn!(
+                    "Retrying request on target: {}, attempt: {}, previous error: {}",
+                    connection_pool_guard[next_selection]._socket_addr,
+                    tried.len() + 1,
+                    e
+                ),
+            }
+
+            _retry = Some(retry);
+            selection = next_selection;
+            tried.push(selection);
+            upstream_body = full(bytes.clone());
+        };
+
+        let mut response = outcome?;
+
+        // A new session starts unless the request was served by the target it is bound to.
+        if affinity != Some(selection)
+            && let Some(session_cookie) = self
+                .session_affinity
+                .as_ref()
+                .and_then(|a| a.session_cookie(&connection_pool_guard[selection]))
+        {
+            response.headers_mut().append(SET_COOKIE, session_cookie);
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bb8::Pool;
+    use http::StatusCode;
+    use http_body_util::{Empty, Full};
+    use hyper::{body::Bytes, client::conn::http1};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        spawn,
+    };
+
+    use super::*;
+    use crate::{
+        config::{ListenerRuleRetryConfiguration, TargetGroupCircuitBreakerConfiguration},
+        connection_manager::ConnectionManager,
+        connection_pool::TargetConnections,
+        test_util::{serve_http, target_group},
+    };
+
+    /// Target answering every request with `fault` once it read the request head.
+    async fn faulty_target<F, Fut>(fault: F) -> SocketAddr
+    where
+        F: Fn(TcpStream) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let fault = Arc::new(fault);
+
+        spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let fault = fault.clone();
+
+                spawn(async move {
+                    if stream.read(&mut [0; 1024]).await.unwrap_or(0) > 0 {
+                        fault(stream).await;
+                    }
+                });
+            }
+        });
+
+        addr
+    }
+
+    async fn write_and_close(mut stream: TcpStream, response: &[u8]) {
+        stream.write_all(response).await.unwrap();
+    }
+
+    fn refusing_target() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    fn get(path: &str) -> Request<Empty<Bytes>> {
+        Request::get(path)
+            .header(HOST, "localhost")
+            .body(Empty::new())
+            .unwrap()
+    }
+
+    fn faulty_handler(balancer: &mut LoadBalancer) -> &mut ListenerRuleHandler {
+        balancer.listener_targets.get_mut("/faulty/").unwrap()
+    }
+
+    /// Load balancer routing `/faulty` to `faulty` and `/healthy` to a working target, with the
+    /// `/faulty` rule adjusted by `configure`.
+    async fn serve_balancer(
+        faulty: SocketAddr,
+        configure: impl FnOnce(&mut LoadBalancer),
+    ) -> (Arc<LoadBalancer>, SocketAddr) {
+        let healthy = serve_http(|_| Response::new(Full::new(Bytes::from("ok")))).await;
+
+        let connection_pools = TargetGroupsConnectionPools::try_from_target_groups(
+            &HashMap::from([
+                ("faulty".to_owned(), target_group(&[faulty])),
+                ("healthy".to_owned(), target_group(&[healthy])),
+            ]),
+            4,
+        )
+        .await
+        .unwrap();
+
+        let listener_rules = ["faulty", "healthy"]
+            .map(|name| ListenerRule {
+                target_group: name.to_owned(),
+                path_prefix: format!("/{}", name),
+                path_rewrite: format!("/{}", name),
+                retry: ListenerRuleRetryConfiguration::default(),
+            })
+            .into();
+
+        let mut balancer = LoadBalancer::new(
+            listener_rules,
+            &connection_pools,
+            &HashMap::new(),
+            LoadBalancingAlgorithm::RoundRobin,
+            Duration::from_millis(500),
+        )
+        .await
+        .unwrap();
+        configure(&mut balancer);
+        let balancer = Arc::new(balancer);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let serving = balancer.clone();
+
+        spawn(async move {
+            while let Ok((stream, client_addr)) = listener.accept().await {
+                spawn(
+                    serving
+                        .clone()
+                        .serve_connection(TokioIo::new(stream), client_addr),
+                );
+            }
+        });
+
+        (balancer, addr)
+    }
+
+    async fn client(addr: SocketAddr) -> http1::SendRequest<Empty<Bytes>> {
+        let (sender, conn) =
+            http1::handshake(TokioIo::new(TcpStream::connect(addr).await.unwrap()))
+                .await
+                .unwrap();
+        spawn(conn);
+
+        sender
+    }
+
+    /// Requests `/faulty` and then `/healthy` over the same client connection.
+    async fn assert_proxy_error(
+        balancer: &LoadBalancer,
+        sender: &mut http1::SendRequest<Empty<Bytes>>,
+        status: StatusCode,
+        kind: &str,
+    ) {
+        sender.ready().await.unwrap();
+        let response = sender.send_request(get("/faulty/")).await.unwrap();
+        assert_eq!(response.status(), status);
+        assert_eq!(balancer.error_counters.get(kind), 1);
+
+        // The failed request leaves the client connection usable.
+        sender.ready().await.unwrap();
+        let response = sender.send_request(get("/healthy/")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    async fn assert_faulty_target(faulty: SocketAddr, status: StatusCode, kind: &str) {
+        let (balancer, addr) = serve_balancer(faulty, |_| ()).await;
+
+        assert_proxy_error(&balancer, &mut client(addr).await, status, kind).await;
+    }
+
+    #[tokio::test]
+    async fn refused_connection_is_bad_gateway() {
+        assert_faulty_target(refusing_target(), StatusCode::BAD_GATEWAY, "connect").await;
+    }
+
+    #[tokio::test]
+    async fn reset_mid_response_is_bad_gateway() {
+        let resetting = faulty_target(|stream| async move {
+            // A zero linger resets the connection on close instead of blocking.
+            #[allow(deprecated)]
+            stream.set_linger(Some(Duration::ZERO)).unwrap();
+            write_and_close(stream, b"HTTP/1.1 200 OK\r\nContent-").await;
+        })
+        .await;
+
+        assert_faulty_target(resetting, StatusCode::BAD_GATEWAY, "send_request").await;
+    }
+
+    #[tokio::test]
+    async fn malformed_response_is_bad_gateway() {
+        let malformed =
+            faulty_target(|stream| write_and_close(stream, b"NOT HTTP AT ALL\r\n\r\n")).await;
+
+        assert_faulty_target(malformed, StatusCode::BAD_GATEWAY, "send_request").await;
+    }
+
+    #[tokio::test]
+    async fn hanging_target_is_gateway_timeout() {
+        let hanging = faulty_target(|stream| async move {
+            std::future::pending::<()>().await;
+            drop(stream);
+        })
+        .await;
+
+        assert_faulty_target(hanging, StatusCode::GATEWAY_TIMEOUT, "timeout").await;
+    }
+
+    #[tokio::test]
+    async fn empty_target_group_is_unavailable() {
+        let (balancer, addr) = serve_balancer(refusing_target(), |balancer| {
+            faulty_handler(balancer)
+                .connection_pool
+                .try_write()
+                .unwrap()
+                .clear();
+        })
+        .await;
+
+        assert_proxy_error(
+            &balancer,
+            &mut client(addr).await,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no_targets",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn open_circuit_is_unavailable() {
+        let (balancer, addr) = serve_balancer(refusing_target(), |balancer| {
+            faulty_handler(balancer).circuit_breaker = Some(CircuitBreaker::from(
+                &TargetGroupCircuitBreakerConfiguration {
+                    consecutive_failures: 1,
+                    ..Default::default()
+                },
+            ));
+        })
+        .await;
+        let mut sender = client(addr).await;
+
+        let response = sender.send_request(get("/faulty/")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+        assert_proxy_error(
+            &balancer,
+            &mut sender,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "circuit_open",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn exhausted_pool_is_unavailable_without_tripping_the_circuit() {
+        let target = serve_http(|_| Response::new(Full::new(Bytes::from("ok")))).await;
+        let (balancer, addr) = serve_balancer(target, |balancer| {
+            let handler = faulty_handler(balancer);
+
+            handler.circuit_breaker = Some(CircuitBreaker::from(
+                &TargetGroupCircuitBreakerConfiguration {
+                    consecutive_failures: 1,
+                    ..Default::default()
+                },
+            ));
+            handler.connection_pool.try_write().unwrap()[0].connection_pool =
+                TargetConnections::Pooled(
+                    Pool::builder()
+                        .max_size(1)
+                        .connection_timeout(Duration::from_millis(100))
+                        .build_unchecked(ConnectionManager::new(target, None)),
+                );
+        })
+        .await;
+
+        let TargetConnections::Pooled(pool) = &balancer.listener_targets["/faulty/"]
+            .connection_pool
+            .read()
+            .await[0]
+            .connection_pool
+        else {
+            unreachable!();
+        };
+        let held = pool.get().await.unwrap();
+        let mut sender = client(addr).await;
+
+        assert_proxy_error(
+            &balancer,
+            &mut sender,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "connect",
+        )
+        .await;
+
+        drop(held);
+
+        sender.ready().await.unwrap();
+        let response = sender.send_request(get("/faulty/")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn truncated_request_body_is_bad_request() {
+        let target = serve_http(|_| Response::new(Full::new(Bytes::from("ok")))).await;
+        let (balancer, addr) = serve_balancer(target, |balancer| {
+            // Retries buffer the body before it is sent to the target.
+            faulty_handler(balancer).retry_policy =
+                RetryPolicy::from(&ListenerRuleRetryConfiguration {
+                    attempts: 2,
+                    ..Default::default()
+                });
+        })
+        .await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /faulty/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nabc")
             .await
-            .expect("Failed to wait for ready connection");
+            .unwrap();
+        stream.shutdown().await.unwrap();
 
-        select! {
-          response_result = target.send_request(client_request) => {
-            let response = response_result.expect("Failed to send request");
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+
+        assert!(response.starts_with(b"HTTP/1.1 400 "));
+        assert_eq!(balancer.error_counters.get("request_body"), 1);
+    }
 
-            let (parts, incoming_body) = response.into_parts();
+    #[tokio::test]
+    async fn only_failures_reaching_the_target_count_as_slow() {
+        let response_time = |circuit_breaker: Option<TargetGroupCircuitBreakerConfiguration>| async move {
+            let (balancer, addr) = serve_balancer(refusing_target(), |balancer| {
+                faulty_handler(balancer).circuit_breaker =
+                    circuit_breaker.as_ref().map(CircuitBreaker::from);
+            })
+            .await;
 
-            let body = incoming_body
-                .collect()
+            client(addr)
+                .await
+                .send_request(get("/faulty/"))
                 .await
-                .expect("Failed to get body")
-                .to_bytes();
+                .unwrap();
+
+            balancer.listener_targets["/faulty/"]
+                .connection_pool
+                .read()
+                .await[0]
+                .load
+                .response_time()
+        };
 
-            let response = Response::from_parts(parts, Full::new(body));
+        assert!(response_time(None).await > 0.0);
 
-            Ok(response)
-          },
-          _ = sleep(self.connection_timeout) => {
-            Ok(Response::builder()
-                .status(http::StatusCode::GATEWAY_TIMEOUT)
-                .body(Full::new(Bytes::new()))
-                .unwrap())
-          }
-        }
+        // Rejected by the circuit breaker before connecting.
+        let rejecting = TargetGroupCircuitBreakerConfiguration {
+            max_requests: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(response_time(Some(rejecting)).await, 0.0);
     }
 }
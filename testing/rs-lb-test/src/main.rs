@@ -8,6 +8,7 @@ use hyper_util::rt::TokioIo;
 use tokio::select;
 use tokio::signal::unix::{SignalKind, signal};
 use tokio::{net::TcpListener, spawn};
+use tokio_rustls::TlsAcceptor;
 
 use crate::config::LoadBalancerConfiguration;
 use crate::connection_pool::TargetGroupsConnectionPools;
@@ -15,24 +16,60 @@ use crate::health_monitor::HealthMonitor;
 use crate::listener::ListenerRule;
 use crate::load_balancer::LoadBalancer;
 use crate::target::{TargetGroup, TargetGroupCreationError};
+use crate::tls::CertificateResolver;
 
+mod body;
 mod cache;
+mod circuit_breaker;
 mod config;
 mod connection_manager;
 mod connection_pool;
 mod health_monitor;
 mod listener;
 mod load_balancer;
+mod outlier_detection;
+mod proxy_error;
+mod retry;
 mod selector;
+mod session_affinity;
 mod target;
-
-async fn listen(listener: TcpListener, balancer: Arc<LoadBalancer>) {
-    while let Ok((stream, _)) = listener.accept().await {
+#[cfg(test)]
+mod test_util;
+mod tls;
+
+async fn listen(
+    listener: TcpListener,
+    balancer: Arc<LoadBalancer>,
+    tls_acceptor: Option<TlsAcceptor>,
+) {
+    while let Ok((stream, client_addr)) = listener.accept().await {
         let balancer_ref = balancer.clone();
-        let conn = TokioIo::new(stream);
-        let handler_fut = balancer_ref.serve_connection(conn);
 
-        spawn(handler_fut);
+        let Some(tls_acceptor) = &tls_acceptor else {
+            let conn = TokioIo::new(stream);
+            let handler_fut = balancer_ref.serve_connection(conn, client_addr);
+
+            spawn(handler_fut);
+
+            continue;
+        };
+
+        let accept_fut = tls_acceptor.accept(stream);
+
+        spawn(async move {
+            match accept_fut.await {
+                Ok(stream) => {
+                    balancer_ref
+                        .serve_connection(TokioIo::new(stream), client_addr)
+                        .await
+                }
+                Err(e) => log::warn!(
+                    "TLS handshake with client: {} failed, error: {}",
+                    client_addr,
+                    e
+                ),
+            }
+        });
     }
 }
 
@@ -54,11 +91,14 @@ async fn run() -> Result<(), Box<dyn Error>> {
     let LoadBalancerConfiguration {
         listener_port,
         connection_timout,
+        load_balancing_algorithm,
         connection_pool_size,
         listener_rules: raw_listener_rules,
         target_groups: raw_target_groups,
         cache_enabled,
         cache_ttl: cache_ttl_ms,
+        cache_max_body_size,
+        tls,
         ..
     } = load_balancer_configuration;
 
@@ -82,37 +122,55 @@ async fn run() -> Result<(), Box<dyn Error>> {
             .await
             .map_err(Box::new)?;
 
-    let health_check_connection_pools =
-        TargetGroupsConnectionPools::try_from_target_groups(&target_groups, 1)
-            .await
-            .map_err(Box::new)?;
-
-    if let Some(health_monitor) =
-        HealthMonitor::new(health_check_connection_pools, &raw_target_groups)
-    {
-        spawn(health_monitor.health_monitor_thread());
-    }
-
     let mut balancer = LoadBalancer::new(
         listener_rules,
         &connection_pools,
+        &raw_target_groups,
+        load_balancing_algorithm,
         Duration::from_millis(connection_timout),
     )
-    .await;
+    .await
+    .map_err(Box::new)?;
+
+    // Started once the session affinity rings were built from every target, a target removed
+    // by the first health checks would be missing from them otherwise.
+    if let Some(health_monitor) = HealthMonitor::new(
+        connection_pools.groups_connection_pools.clone(),
+        &raw_target_groups,
+    )
+    .await
+    .map_err(Box::new)?
+    {
+        spawn(health_monitor.health_monitor_thread());
+    }
 
     if cache_enabled {
-        balancer = balancer.with_cache(Duration::from_millis(cache_ttl_ms));
+        balancer = balancer.with_cache(Duration::from_millis(cache_ttl_ms), cache_max_body_size);
     }
 
     let balancer_arc = Arc::new(balancer);
 
+    let tls_acceptor = if tls.enabled {
+        let certificate_resolver = CertificateResolver::new(&tls).map_err(Box::new)?;
+
+        spawn(
+            certificate_resolver
+                .clone()
+                .reload_thread(Duration::from_millis(tls.reload_interval)),
+        );
+
+        Some(certificate_resolver.acceptor().map_err(Box::new)?)
+    } else {
+        None
+    };
+
     log::info!("Serving connections at: 0.0.0.0:{}", listener_port);
 
     let mut sigint = signal(SignalKind::interrupt())?;
     let mut sigterm = signal(SignalKind::terminate())?;
 
     select!(
-      _ = listen(listener, balancer_arc) => {},
+      _ = listen(listener, balancer_arc, tls_acceptor) => {},
       _ = sigint.recv() => {
         log::info!("Recieved SIGINT, shutting down...")
       },
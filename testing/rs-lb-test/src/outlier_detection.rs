@@ -0,0 +1,316 @@
+use std::{sync::Mutex, time::Duration};
+
+use hyper::body::Body;
+use tokio::time::Instant;
+
+use crate::{
+    config::TargetGroupOutlierDetectionConfiguration, connection_pool::TargetConnectionPool,
+};
+
+#[derive(Debug)]
+struct OutlierState {
+    consecutive_errors: usize,
+    window_start: Instant,
+    window_requests: usize,
+    window_errors: usize,
+    ejected_until: Option<Instant>,
+    ejection_count: u32,
+}
+
+/// Passive health of a single target, kept with its connection pool so it follows the target
+/// when the health monitor moves it in and out of the target group.
+#[derive(Debug)]
+pub struct TargetOutlierStatus {
+    inner: Mutex<OutlierState>,
+}
+
+impl TargetOutlierStatus {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(OutlierState {
+                consecutive_errors: 0,
+                window_start: Instant::now(),
+                window_requests: 0,
+                window_errors: 0,
+                ejected_until: None,
+                ejection_count: 0,
+            }),
+        }
+    }
+
+    pub fn is_ejected(&self) -> bool {
+        self.inner
+            .lock()
+            .expect("Failed to aquire outlier status lock")
+            .ejected_until
+            .is_some_and(|until| until > Instant::now())
+    }
+}
+
+impl Default for TargetOutlierStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct OutlierDetector {
+    consecutive_errors: usize,
+    error_rate: Option<usize>,
+    error_rate_window: Duration,
+    error_rate_minimum_requests: usize,
+    base_ejection_time: Duration,
+    max_ejection_time: Duration,
+    max_ejection_percent: usize,
+    ejection_lock: Mutex<()>,
+}
+
+impl From<&TargetGroupOutlierDetectionConfiguration> for OutlierDetector {
+    fn from(value: &TargetGroupOutlierDetectionConfiguration) -> Self {
+        Self {
+            consecutive_errors: value.consecutive_errors,
+            error_rate: value.error_rate,
+            error_rate_window: Duration::from_millis(value.error_rate_window),
+            error_rate_minimum_requests: value.error_rate_minimum_requests,
+            base_ejection_time: Duration::from_millis(value.base_ejection_time),
+            max_ejection_time: Duration::from_millis(value.max_ejection_time),
+            max_ejection_percent: value.max_ejection_percent,
+            ejection_lock: Mutex::new(()),
+        }
+    }
+}
+
+impl OutlierDetector {
+    /// Picks the first target from `selection` onwards which is not ejected. Falls back to
+    /// `selection` when every target is ejected, e.g. after the health monitor removed others.
+    pub fn select<T>(&self, pools: &[TargetConnectionPool<T>], selection: usize) -> usize
+    where
+        T: Send + Sync + Body + Unpin + 'static,
+        T::Data: Send,
+        T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
+    {
+        (0..pools.len())
+            .map(|offset| (selection + offset) % pools.len())
+            .find(|idx| !pools[*idx].outlier_status.is_ejected())
+            .unwrap_or(selection)
+    }
+
+    /// Records the outcome of a request sent to the target at `idx`, ejecting the target if it
+    /// became an outlier and the group's ejection cap allows it.
+    pub fn record<T>(&self, pools: &[TargetConnectionPool<T>], idx: usize, is_error: bool)
+    where
+        T: Send + Sync + Body + Unpin + 'static,
+        T::Data: Send,
+        T::Error: Into<Box<dyn serde::ser::StdError + Send + Sync>>,
+    {
+        let Some(target) = pools.get(idx) else {
+            return;
+        };
+
+        let mut state = target
+            .outlier_status
+            .inner
+            .lock()
+            .expect("Failed to aquire outlier status lock");
+
+        let now = Instant::now();
+
+        if now - state.window_start >= self.error_rate_window {
+            // A clean window slowly forgives earlier ejections.
+            if state.window_errors == 0 && state.window_requests > 0 {
+                state.ejection_count = state.ejection_count.saturating_sub(1);
+            }
+
+            state.window_start = now;
+            state.window_requests = 0;
+            state.window_errors = 0;
+        }
+
+        state.window_requests += 1;
+
+        if is_error {
+            state.consecutive_errors += 1;
+            state.window_errors += 1;
+        } else {
+            state.consecutive_errors = 0;
+        }
+
+        if state.ejected_until.is_some_and(|until| until > now) {
+            return;
+        }
+
+        let consecutive_exceeded = state.consecutive_errors >= self.consecutive_errors;
+        let rate_exceeded = self.error_rate.is_some_and(|rate| {
+            state.window_requests >= self.error_rate_minimum_requests
+                && state.window_errors * 100 >= rate * state.window_requests
+        });
+
+        if !consecutive_exceeded && !rate_exceeded {
+            return;
+        }
+
+        // Counting ejections locks every target's state, this one's included, so it is released
+        // first and locked again once the ejection is allowed.
+        drop(state);
+
+        let _ejection_guard = self
+            .ejection_lock
+            .lock()
+            .expect("Failed to aquire ejection lock");
+
+        let ejected = pools
+            .iter()
+            .filter(|p| p.outlier_status.is_ejected())
+            .count();
+
+        if ejected + 1 >= pools.len()
+            || (ejected + 1) * 100 > self.max_ejection_percent * pools.len()
+        {
+            log::debug!(
+                "Not ejecting outlier target: {}, maximum ejection percent reached",
+                target._socket_addr
+            );
+            return;
+        }
+
+        let mut state = target
+            .outlier_status
+            .inner
+            .lock()
+            .expect("Failed to aquire outlier status lock");
+
+        let ejection_time = self
+            .base_ejection_time
+            .saturating_mul(2u32.saturating_pow(state.ejection_count))
+            .min(self.max_ejection_time);
+
+        log::warn!(
+            "Ejecting outlier target: {} for {}ms, consecutive errors: {}, window errors: {}/{}",
+            target._socket_addr,
+            ejection_time.as_millis(),
+            state.consecutive_errors,
+            state.window_errors,
+            state.window_requests
+        );
+
+        state.ejected_until = Some(now + ejection_time);
+        state.ejection_count += 1;
+        state.consecutive_errors = 0;
+        state.window_start = now;
+        state.window_requests = 0;
+        state.window_errors = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::test_util::target_group_pools;
+
+    fn detector(
+        configure: impl FnOnce(&mut TargetGroupOutlierDetectionConfiguration),
+    ) -> OutlierDetector {
+        let mut config = TargetGroupOutlierDetectionConfiguration {
+            enabled: true,
+            consecutive_errors: 3,
+            base_ejection_time: 100,
+            max_ejection_time: 300,
+            max_ejection_percent: 100,
+            ..Default::default()
+        };
+        configure(&mut config);
+
+        OutlierDetector::from(&config)
+    }
+
+    /// Pools for `count` targets which are never connected to.
+    async fn pools(count: u16) -> Vec<TargetConnectionPool<crate::connection_pool::UpstreamBody>> {
+        let addrs = (1..=count)
+            .map(|port| SocketAddr::from(([127, 0, 0, 1], port)))
+            .collect::<Vec<_>>();
+        let pools = target_group_pools(&addrs).await;
+
+        std::mem::take(&mut *pools.write().await)
+    }
+
+    fn record_all(
+        detector: &OutlierDetector,
+        pools: &[TargetConnectionPool<crate::connection_pool::UpstreamBody>],
+        idx: usize,
+        outcomes: &[bool],
+    ) {
+        outcomes
+            .iter()
+            .for_each(|is_error| detector.record(pools, idx, *is_error));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ejects_after_consecutive_errors() {
+        let detector = detector(|_| ());
+        let pools = pools(4).await;
+
+        record_all(&detector, &pools, 0, &[true, true, false, true, true]);
+        assert!(!pools[0].outlier_status.is_ejected());
+
+        detector.record(&pools, 0, true);
+        assert!(pools[0].outlier_status.is_ejected());
+        assert_eq!(detector.select(&pools, 0), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ejects_on_error_rate_once_minimum_requests_reached() {
+        let detector = detector(|c| {
+            c.consecutive_errors = 100;
+            c.error_rate = Some(50);
+            c.error_rate_minimum_requests = 4;
+        });
+        let pools = pools(4).await;
+
+        record_all(&detector, &pools, 0, &[true, false, true]);
+        assert!(!pools[0].outlier_status.is_ejected());
+
+        detector.record(&pools, 0, false);
+        assert!(pools[0].outlier_status.is_ejected());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn respects_max_ejection_percent() {
+        let detector = detector(|c| c.max_ejection_percent = 50);
+        let pools = pools(4).await;
+
+        (0..3).for_each(|idx| record_all(&detector, &pools, idx, &[true; 3]));
+
+        assert!(pools[0].outlier_status.is_ejected());
+        assert!(pools[1].outlier_status.is_ejected());
+        assert!(!pools[2].outlier_status.is_ejected());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn never_ejects_the_last_target() {
+        let detector = detector(|_| ());
+        let pools = pools(2).await;
+
+        record_all(&detector, &pools, 0, &[true; 3]);
+        record_all(&detector, &pools, 1, &[true; 3]);
+
+        assert!(pools[0].outlier_status.is_ejected());
+        assert!(!pools[1].outlier_status.is_ejected());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ejection_time_doubles_up_to_the_maximum() {
+        let detector = detector(|_| ());
+        let pools = pools(4).await;
+
+        for ejection_time in [100, 200, 300, 300] {
+            record_all(&detector, &pools, 0, &[true; 3]);
+
+            tokio::time::advance(Duration::from_millis(ejection_time - 1)).await;
+            assert!(pools[0].outlier_status.is_ejected());
+
+            tokio::time::advance(Duration::from_millis(1)).await;
+            assert!(!pools[0].outlier_status.is_ejected());
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::net::SocketAddr;
+
+use dashmap::DashMap;
+use http::{Response, StatusCode, uri::InvalidUri};
+
+use crate::{
+    body::{ResponseBody, empty},
+    connection_manager::ConnectionManagerError,
+};
+
+/// Failure to proxy a request, answered with an empty response carrying [`ProxyError::status`].
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyError {
+    #[error("No listener rule matches the request path")]
+    NoMatchingRule,
+    #[error("Request uri has no path")]
+    MissingPath,
+    #[error("Failed to rewrite request uri, error: {0}")]
+    InvalidUri(InvalidUri),
+    #[error("Failed to build upstream request, error: {0}")]
+    BuildRequest(http::Error),
+    #[error("Failed to read request body, error: {0}")]
+    RequestBody(hyper::Error),
+    #[error("No targets available")]
+    NoTargets,
+    #[error("Circuit of target: {0} is open")]
+    CircuitOpen(SocketAddr),
+    #[error("Too many requests pending for target: {0}")]
+    TooManyPending(SocketAddr),
+    #[error("Failed to get connection to target: {0}, error: {1}")]
+    Connect(SocketAddr, bb8::RunError<ConnectionManagerError>),
+    #[error("Connection to target: {0} closed, error: {1}")]
+    ConnectionClosed(SocketAddr, hyper::Error),
+    #[error("Failed to send request to target: {0}, error: {1}")]
+    SendRequest(SocketAddr, hyper::Error),
+    #[error("Target: {0} did not respond in time")]
+    Timeout(SocketAddr),
+}
+
+impl ProxyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NoMatchingRule => StatusCode::NOT_FOUND,
+            Self::MissingPath | Self::InvalidUri(_) | Self::RequestBody(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::BuildRequest(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            // The pool timing out means every connection to the target is in use.
+            Self::NoTargets
+            | Self::CircuitOpen(_)
+            | Self::TooManyPending(_)
+            | Self::Connect(_, bb8::RunError::TimedOut) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Connect(..) | Self::ConnectionClosed(..) | Self::SendRequest(..) => {
+                StatusCode::BAD_GATEWAY
+            }
+            Self::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// Short name used as the counter key and in logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NoMatchingRule => "no_matching_rule",
+            Self::MissingPath => "missing_path",
+            Self::InvalidUri(_) => "invalid_uri",
+            Self::BuildRequest(_) => "build_request",
+            Self::RequestBody(_) => "request_body",
+            Self::NoTargets => "no_targets",
+            Self::CircuitOpen(_) => "circuit_open",
+            Self::TooManyPending(_) => "too_many_pending",
+            Self::Connect(..) => "connect",
+            Self::ConnectionClosed(..) => "connection_closed",
+            Self::SendRequest(..) => "send_request",
+            Self::Timeout(_) => "timeout",
+        }
+    }
+
+    /// Whether another target may succeed where this one failed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::CircuitOpen(_)
+                | Self::TooManyPending(_)
+                | Self::Connect(..)
+                | Self::ConnectionClosed(..)
+                | Self::SendRequest(..)
+                | Self::Timeout(_)
+        )
+    }
+
+    pub fn to_response(&self) -> Response<ResponseBody> {
+        let mut response = Response::new(empty());
+        *response.status_mut() = self.status();
+
+        response
+    }
+}
+
+/// Number of requests failed with each [`ProxyError::kind`] since startup.
+#[derive(Default)]
+pub struct ProxyErrorCounters {
+    inner: DashMap<&'static str, u64>,
+}
+
+impl ProxyErrorCounters {
+    /// Counts `error` and returns the total for its kind.
+    pub fn increment(&self, error: &ProxyError) -> u64 {
+        let mut count = self.inner.entry(error.kind()).or_insert(0);
+        *count += 1;
+
+        *count
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, kind: &str) -> u64 {
+        self.inner.get(kind).map(|count| *count).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Request;
+
+    use super::*;
+
+    #[test]
+    fn pool_timeout_is_unavailable_and_connect_error_bad_gateway() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1));
+        let refused = ConnectionManagerError::UnableToConnect(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused,
+        ));
+
+        assert_eq!(
+            ProxyError::Connect(addr, bb8::RunError::TimedOut).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            ProxyError::Connect(addr, bb8::RunError::User(refused)).status(),
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[test]
+    fn failing_to_build_request_is_internal_error() {
+        let error = Request::get("/")
+            .header("invalid header", "")
+            .body(())
+            .unwrap_err();
+
+        assert_eq!(
+            ProxyError::BuildRequest(error).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use http::{Method, StatusCode};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Incoming};
+
+use crate::config::ListenerRuleRetryConfiguration;
+
+/// Counts towards an in flight counter until dropped.
+pub struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        Self(counter)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct RetryPolicy {
+    attempts: usize,
+    per_try_timeout: Option<Duration>,
+    allow_non_idempotent: bool,
+    max_body_size: u64,
+    budget_percent: usize,
+    min_retry_concurrency: usize,
+    active: AtomicUsize,
+    retrying: AtomicUsize,
+}
+
+impl From<&ListenerRuleRetryConfiguration> for RetryPolicy {
+    fn from(value: &ListenerRuleRetryConfiguration) -> Self {
+        Self {
+            attempts: value.attempts.max(1),
+            per_try_timeout: value.per_try_timeout.map(Duration::from_millis),
+            allow_non_idempotent: value.allow_non_idempotent,
+            max_body_size: value.max_body_size,
+            budget_percent: value.budget_percent,
+            min_retry_concurrency: value.min_retry_concurrency,
+            active: AtomicUsize::new(0),
+            retrying: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    pub fn per_try_timeout(&self) -> Option<Duration> {
+        self.per_try_timeout
+    }
+
+    /// Whether the request may be sent more than once. Its body has to be buffered for that,
+    /// so bodies without a known length below the limit are streamed and never retried.
+    pub fn allows(&self, method: &Method, body: &Incoming) -> bool {
+        self.attempts > 1
+            && (self.allow_non_idempotent || is_idempotent(method))
+            && body
+                .size_hint()
+                .upper()
+                .is_some_and(|size| size <= self.max_body_size)
+    }
+
+    pub fn start_request(&self) -> InFlight<'_> {
+        InFlight::new(&self.active)
+    }
+
+    /// Takes a slot in the retry budget, `None` when retries in flight already use it up.
+    pub fn start_retry(&self) -> Option<InFlight<'_>> {
+        let budget = (self.active.load(Ordering::Relaxed) * self.budget_percent / 100)
+            .max(self.min_retry_concurrency);
+
+        self.retrying
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |retrying| {
+                (retrying < budget).then_some(retrying + 1)
+            })
+            .ok()
+            .map(|_| InFlight(&self.retrying))
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Buffers a request body so it can be sent to more than one target.
+pub async fn buffer_body(body: Incoming) -> Result<Bytes, hyper::Error> {
+    Ok(body.collect().await?.to_bytes())
+}
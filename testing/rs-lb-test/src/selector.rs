@@ -1,4 +1,118 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::{
+    config::LoadBalancingAlgorithm,
+    connection_pool::{TargetConnectionPool, UpstreamBody},
+};
+
+/// Weight of the latest sample in the response time moving average.
+const RESPONSE_TIME_DECAY: f64 = 0.3;
+
+/// Requests in flight and the moving average of the response time of a single target, kept with
+/// its connection pool so it follows the target when the health monitor moves it.
+#[derive(Debug)]
+pub struct TargetLoad {
+    outstanding: AtomicUsize,
+    // Microseconds, stored as the bits of an f64.
+    response_time: AtomicU64,
+}
+
+impl TargetLoad {
+    pub fn new() -> Self {
+        Self {
+            outstanding: AtomicUsize::new(0),
+            response_time: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    pub fn response_time(&self) -> f64 {
+        f64::from_bits(self.response_time.load(Ordering::Relaxed))
+    }
+
+    /// Counts a request as outstanding until the returned guard is dropped.
+    pub fn start(self: &Arc<Self>) -> OutstandingRequest {
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+
+        OutstandingRequest {
+            load: self.clone(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn record_response_time(&self, response_time: Duration) {
+        let sample = response_time.as_micros() as f64;
+
+        let _ = self
+            .response_time
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                let average = f64::from_bits(bits);
+
+                let average = if average == 0.0 {
+                    sample
+                } else {
+                    average + RESPONSE_TIME_DECAY * (sample - average)
+                };
+
+                Some(average.to_bits())
+            });
+    }
+}
+
+impl Default for TargetLoad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct OutstandingRequest {
+    load: Arc<TargetLoad>,
+    started: Instant,
+}
+
+impl OutstandingRequest {
+    /// Adds the time since the request started to the target's response time. Failed requests
+    /// are dropped without finishing and record the attempt timeout instead, so quick failures
+    /// do not make the target look fast.
+    pub fn finish(self) {
+        self.load.record_response_time(self.started.elapsed());
+    }
+}
+
+impl Drop for OutstandingRequest {
+    fn drop(&mut self) {
+        self.load.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Picks the target for the next request out of a non-empty target group.
+pub trait Selector: Send + Sync {
+    fn select(&self, pools: &[TargetConnectionPool<UpstreamBody>]) -> usize;
+}
+
+pub fn new_selector(algorithm: LoadBalancingAlgorithm) -> Box<dyn Selector> {
+    match algorithm {
+        LoadBalancingAlgorithm::RoundRobin | LoadBalancingAlgorithm::Sticky => {
+            Box::new(RoundRobin::new())
+        }
+        LoadBalancingAlgorithm::WeightedRoundRobin => Box::new(WeightedRoundRobin::new()),
+        LoadBalancingAlgorithm::LeastRequests => Box::new(LeastRequests::new()),
+        LoadBalancingAlgorithm::LeastResponseTime => Box::new(LeastResponseTime::new()),
+        LoadBalancingAlgorithm::Random => Box::new(Random),
+        LoadBalancingAlgorithm::PowerOfTwoChoices => Box::new(PowerOfTwoChoices),
+    }
+}
 
 pub struct RoundRobin(AtomicUsize);
 
@@ -11,3 +125,121 @@ impl RoundRobin {
         self.0.fetch_add(1, Ordering::Relaxed) % limit
     }
 }
+
+impl Selector for RoundRobin {
+    fn select(&self, pools: &[TargetConnectionPool<UpstreamBody>]) -> usize {
+        self.next_wrapping(pools.len())
+    }
+}
+
+/// Round robin where every target gets `weight` consecutive turns per cycle.
+pub struct WeightedRoundRobin(RoundRobin);
+
+impl WeightedRoundRobin {
+    pub fn new() -> Self {
+        Self(RoundRobin::new())
+    }
+}
+
+impl Selector for WeightedRoundRobin {
+    fn select(&self, pools: &[TargetConnectionPool<UpstreamBody>]) -> usize {
+        let total = pools.iter().map(|p| p.weight).sum::<usize>();
+
+        if total == 0 {
+            return self.0.next_wrapping(pools.len());
+        }
+
+        let mut turn = self.0.next_wrapping(total);
+
+        pools
+            .iter()
+            .position(|p| {
+                if turn < p.weight {
+                    true
+                } else {
+                    turn -= p.weight;
+                    false
+                }
+            })
+            .unwrap_or(0)
+    }
+}
+
+/// Picks the target with the lowest score, starting the scan at a rotating offset so ties are
+/// spread over the tied targets instead of always going to the first one.
+fn least_by<F>(offset: &RoundRobin, pools: &[TargetConnectionPool<UpstreamBody>], score: F) -> usize
+where
+    F: Fn(&TargetConnectionPool<UpstreamBody>) -> f64,
+{
+    let start = offset.next_wrapping(pools.len());
+
+    (0..pools.len())
+        .map(|idx| (start + idx) % pools.len())
+        .min_by(|a, b| score(&pools[*a]).total_cmp(&score(&pools[*b])))
+        .unwrap_or(start)
+}
+
+pub struct LeastRequests(RoundRobin);
+
+impl LeastRequests {
+    pub fn new() -> Self {
+        Self(RoundRobin::new())
+    }
+}
+
+impl Selector for LeastRequests {
+    fn select(&self, pools: &[TargetConnectionPool<UpstreamBody>]) -> usize {
+        least_by(&self.0, pools, |p| p.load.outstanding() as f64)
+    }
+}
+
+/// Scores targets by their average response time multiplied by the requests they already have
+/// in flight, so a fast target is not flooded. Targets without responses yet are tried first,
+/// failed attempts count as taking the whole attempt timeout.
+pub struct LeastResponseTime(RoundRobin);
+
+impl LeastResponseTime {
+    pub fn new() -> Self {
+        Self(RoundRobin::new())
+    }
+}
+
+impl Selector for LeastResponseTime {
+    fn select(&self, pools: &[TargetConnectionPool<UpstreamBody>]) -> usize {
+        least_by(&self.0, pools, |p| {
+            p.load.response_time() * (p.load.outstanding() + 1) as f64
+        })
+    }
+}
+
+pub struct Random;
+
+impl Selector for Random {
+    fn select(&self, pools: &[TargetConnectionPool<UpstreamBody>]) -> usize {
+        rand::random_range(0..pools.len())
+    }
+}
+
+/// Picks two distinct targets at random and keeps the one with fewer outstanding requests.
+pub struct PowerOfTwoChoices;
+
+impl Selector for PowerOfTwoChoices {
+    fn select(&self, pools: &[TargetConnectionPool<UpstreamBody>]) -> usize {
+        if pools.len() < 2 {
+            return 0;
+        }
+
+        let first = rand::random_range(0..pools.len());
+        let mut second = rand::random_range(0..pools.len() - 1);
+
+        if second >= first {
+            second += 1;
+        }
+
+        if pools[second].load.outstanding() < pools[first].load.outstanding() {
+            second
+        } else {
+            first
+        }
+    }
+}
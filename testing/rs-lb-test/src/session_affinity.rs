@@ -0,0 +1,250 @@
+use std::{net::SocketAddr, str::FromStr, time::Duration};
+
+use http::{
+    HeaderMap, HeaderName, HeaderValue,
+    header::{COOKIE, InvalidHeaderName},
+};
+
+use crate::{
+    config::{SessionAffinityMode, TargetGroupSessionAffinityConfiguration},
+    connection_pool::{TargetConnectionPool, UpstreamBody},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SessionAffinityError {
+    #[error("HEADER session affinity requires a header name")]
+    MissingHeader,
+    #[error("Invalid session affinity header name, error: {0}")]
+    InvalidHeader(InvalidHeaderName),
+}
+
+/// FNV-1a followed by the murmur3 finalizer to spread similar keys over the ring. Unlike
+/// `DefaultHasher` it gives the same value in every process, so session cookies and ring
+/// positions survive restarts and agree between load balancer instances.
+fn hash(bytes: &[u8]) -> u64 {
+    let hash = bytes.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51afd7ed558ccd);
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xc4ceb9fe1a85ec53);
+
+    hash ^ (hash >> 33)
+}
+
+/// Names a target in the session cookie without exposing its address.
+fn target_id(socket_addr: &SocketAddr) -> String {
+    format!("{:016x}", hash(socket_addr.to_string().as_bytes()))
+}
+
+fn position_available(
+    pools: &[TargetConnectionPool<UpstreamBody>],
+    predicate: impl Fn(&TargetConnectionPool<UpstreamBody>) -> bool,
+) -> Option<usize> {
+    pools
+        .iter()
+        .position(|p| predicate(p) && !p.outlier_status.is_ejected())
+}
+
+fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(n, _)| *n == name)
+        .map(|(_, v)| v)
+}
+
+/// Consistent hash ring over every target the group started with. Targets removed by the
+/// health monitor or ejected as outliers are skipped during lookup instead of rebuilding the
+/// ring, so only the keys which mapped to them move, and they move back once it returns.
+pub struct HashRing {
+    points: Vec<(u64, SocketAddr)>,
+}
+
+impl HashRing {
+    fn new(pools: &[TargetConnectionPool<UpstreamBody>], virtual_nodes: usize) -> Self {
+        let mut points: Vec<(u64, SocketAddr)> = pools
+            .iter()
+            .flat_map(|p| {
+                (0..virtual_nodes * p.weight).map(|idx| {
+                    (
+                        hash(format!("{}#{}", p._socket_addr, idx).as_bytes()),
+                        p._socket_addr,
+                    )
+                })
+            })
+            .collect();
+
+        points.sort();
+
+        Self { points }
+    }
+
+    fn lookup(&self, key: u64, pools: &[TargetConnectionPool<UpstreamBody>]) -> Option<usize> {
+        let start = self.points.partition_point(|(point, _)| *point < key);
+
+        (0..self.points.len())
+            .map(|offset| self.points[(start + offset) % self.points.len()].1)
+            .find_map(|socket_addr| position_available(pools, |p| p._socket_addr == socket_addr))
+    }
+}
+
+pub enum HashKey {
+    ClientIp,
+    Header(HeaderName),
+}
+
+pub enum SessionAffinity {
+    Cookie { name: String, ttl: Duration },
+    Hash { key: HashKey, ring: HashRing },
+}
+
+impl SessionAffinity {
+    pub fn new(
+        config: &TargetGroupSessionAffinityConfiguration,
+        pools: &[TargetConnectionPool<UpstreamBody>],
+    ) -> Result<Option<Self>, SessionAffinityError> {
+        let key = match config.mode {
+            SessionAffinityMode::None => return Ok(None),
+            SessionAffinityMode::Cookie => {
+                return Ok(Some(Self::Cookie {
+                    name: config.cookie_name.clone(),
+                    ttl: Duration::from_millis(config.session_ttl),
+                }));
+            }
+            SessionAffinityMode::ClientIp => HashKey::ClientIp,
+            SessionAffinityMode::Header => HashKey::Header(
+                HeaderName::from_str(
+                    config
+                        .header
+                        .as_ref()
+                        .ok_or(SessionAffinityError::MissingHeader)?,
+                )
+                .map_err(SessionAffinityError::InvalidHeader)?,
+            ),
+        };
+
+        Ok(Some(Self::Hash {
+            key,
+            ring: HashRing::new(pools, config.virtual_nodes),
+        }))
+    }
+
+    /// Returns the target the request's session is bound to, if it is still available. `None`
+    /// leaves the choice to the target group's selector.
+    pub fn select(
+        &self,
+        headers: &HeaderMap,
+        client_addr: &SocketAddr,
+        pools: &[TargetConnectionPool<UpstreamBody>],
+    ) -> Option<usize> {
+        match self {
+            Self::Cookie { name, .. } => {
+                let id = cookie_value(headers, name)?;
+
+                position_available(pools, |p| target_id(&p._socket_addr) == id)
+            }
+            Self::Hash { key, ring } => {
+                let key = match key {
+                    HashKey::ClientIp => hash(client_addr.ip().to_string().as_bytes()),
+                    HashKey::Header(name) => hash(headers.get(name)?.as_bytes()),
+                };
+
+                ring.lookup(key, pools)
+            }
+        }
+    }
+
+    /// `Set-Cookie` binding a new session to `target`, only issued in cookie mode.
+    pub fn session_cookie(
+        &self,
+        target: &TargetConnectionPool<UpstreamBody>,
+    ) -> Option<HeaderValue> {
+        let Self::Cookie { name, ttl } = self else {
+            return None;
+        };
+
+        HeaderValue::from_str(
+            format!(
+                "{}={}; Max-Age={}; Path=/; HttpOnly",
+                name,
+                target_id(&target._socket_addr),
+                // Rounded up, a zero Max-Age would expire the cookie right away.
+                ttl.as_millis().div_ceil(1000).max(1)
+            )
+            .as_str(),
+        )
+        .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::target_group_pools;
+
+    #[tokio::test]
+    async fn session_cookie_outlives_sub_second_ttl() {
+        let pools = target_group_pools(&[SocketAddr::from(([127, 0, 0, 1], 1))]).await;
+        let pools = pools.read().await;
+
+        let max_age = |ttl| {
+            let affinity = SessionAffinity::Cookie {
+                name: "LB_SESSION".to_owned(),
+                ttl: Duration::from_millis(ttl),
+            };
+            let cookie = affinity.session_cookie(&pools[0]).unwrap();
+
+            cookie
+                .to_str()
+                .unwrap()
+                .split("; ")
+                .nth(1)
+                .unwrap()
+                .to_owned()
+        };
+
+        assert_eq!(max_age(1), "Max-Age=1");
+        assert_eq!(max_age(1500), "Max-Age=2");
+        assert_eq!(max_age(30000), "Max-Age=30");
+    }
+
+    #[tokio::test]
+    async fn removing_a_target_only_moves_its_keys() {
+        let addrs = (1..=4)
+            .map(|port| SocketAddr::from(([127, 0, 0, 1], port)))
+            .collect::<Vec<_>>();
+        let pools = target_group_pools(&addrs).await;
+        let mut pools = pools.write().await;
+
+        let ring = HashRing::new(&pools, 100);
+        let keys = (0..1000)
+            .map(|key| hash(key.to_string().as_bytes()))
+            .collect::<Vec<_>>();
+        let lookup = |pools: &[TargetConnectionPool<UpstreamBody>]| {
+            keys.iter()
+                .map(|key| pools[ring.lookup(*key, pools).unwrap()]._socket_addr)
+                .collect::<Vec<_>>()
+        };
+
+        let before = lookup(&pools);
+        assert!(addrs.iter().all(|addr| before.contains(addr)));
+
+        let removed = pools.remove(1);
+        let after = lookup(&pools);
+
+        for (before, after) in before.iter().zip(after.iter()) {
+            if *before == removed._socket_addr {
+                assert_ne!(after, before);
+            } else {
+                assert_eq!(after, before);
+            }
+        }
+
+        pools.insert(1, removed);
+        assert_eq!(lookup(&pools), before);
+    }
+}
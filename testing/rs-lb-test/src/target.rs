@@ -1,13 +1,21 @@
-use crate::config::TargetGroupConfiguration;
+use crate::{
+    config::{TargetGroupConfiguration, UpstreamProtocol},
+    tls::{TargetGroupTls, TlsError},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum TargetGroupCreationError {
     #[error("Failed to parse target: {0}")]
     ParsingTargetsFailed(String),
+    #[error("Invalid TLS configuration: {0}")]
+    Tls(TlsError),
 }
 
 pub struct TargetGroup {
     pub targets: Vec<Target>,
+    pub max_connections: Option<u32>,
+    pub protocol: UpstreamProtocol,
+    pub tls: Option<TargetGroupTls>,
 }
 
 impl TryFrom<&TargetGroupConfiguration> for TargetGroup {
@@ -21,7 +29,20 @@ impl TryFrom<&TargetGroupConfiguration> for TargetGroup {
             .collect::<Result<Vec<Target>, String>>()
             .map_err(TargetGroupCreationError::ParsingTargetsFailed)?;
 
-        Ok(Self { targets })
+        Ok(Self {
+            targets,
+            max_connections: value
+                .circuit_breaker
+                .max_connections
+                .filter(|_| value.circuit_breaker.enabled),
+            protocol: value.protocol,
+            tls: value
+                .tls
+                .enabled
+                .then(|| TargetGroupTls::new(&value.tls, value.protocol))
+                .transpose()
+                .map_err(TargetGroupCreationError::Tls)?,
+        })
     }
 }
 
@@ -29,13 +50,19 @@ pub struct Target {
     pub hostname: String,
     pub port: u16,
     pub uri: String,
+    pub weight: usize,
 }
 
 impl TryFrom<&str> for Target {
     type Error = String;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let (hostname, suffix) = value.split_once(":").ok_or(value.to_owned())?;
+        let (address, weight) = match value.split_once(";weight=") {
+            Some((address, weight)) => (address, weight.parse().map_err(|_| value.to_owned())?),
+            None => (value, 1),
+        };
+
+        let (hostname, suffix) = address.split_once(":").ok_or(value.to_owned())?;
         let (port, uri) = if suffix.contains("/") {
             suffix.split_once("/").ok_or(value.to_owned())?
         } else {
@@ -48,6 +75,7 @@ impl TryFrom<&str> for Target {
                 .parse()
                 .map_err(|_| value.trim_matches('/').to_owned())?,
             uri: uri.to_owned(),
+            weight,
         })
     }
 }
@@ -0,0 +1,82 @@
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+
+use http::{Request, Response};
+use http_body_util::Full;
+use hyper::{
+    body::{Bytes, Incoming},
+    service::service_fn,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use tokio::{net::TcpListener, spawn, sync::RwLock};
+
+use crate::{
+    config::UpstreamProtocol,
+    connection_pool::{TargetConnectionPool, TargetGroupsConnectionPools, UpstreamBody},
+    target::{Target, TargetGroup},
+};
+
+/// HTTP/1 and HTTP/2 server on an ephemeral local port answering every request with `respond`.
+pub async fn serve_http<F>(respond: F) -> SocketAddr
+where
+    F: Fn(Request<Incoming>) -> Response<Full<Bytes>> + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let respond = Arc::new(respond);
+
+    spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let respond = respond.clone();
+
+            spawn(async move {
+                auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(
+                        TokioIo::new(stream),
+                        service_fn(move |request| {
+                            let response = respond(request);
+
+                            async move { Ok::<_, Infallible>(response) }
+                        }),
+                    )
+                    .await
+            });
+        }
+    });
+
+    addr
+}
+
+/// Plain HTTP/1 target group with the given targets.
+pub fn target_group(targets: &[SocketAddr]) -> TargetGroup {
+    TargetGroup {
+        targets: targets
+            .iter()
+            .map(|addr| Target {
+                hostname: addr.ip().to_string(),
+                port: addr.port(),
+                uri: String::new(),
+                weight: 1,
+            })
+            .collect(),
+        max_connections: None,
+        protocol: UpstreamProtocol::Http1,
+        tls: None,
+    }
+}
+
+/// Connection pools for a single group named `group` with the given targets.
+pub async fn target_group_pools(
+    targets: &[SocketAddr],
+) -> Arc<RwLock<Vec<TargetConnectionPool<UpstreamBody>>>> {
+    TargetGroupsConnectionPools::try_from_target_groups(
+        &HashMap::from([("group".to_owned(), target_group(targets))]),
+        4,
+    )
+    .await
+    .unwrap()
+    .get_pool_for_group("group")
+    .unwrap()
+}
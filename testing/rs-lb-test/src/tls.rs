@@ -0,0 +1,590 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    fs, io,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use rustls::{
+    ClientConfig, RootCertStore, ServerConfig,
+    crypto::{CryptoProvider, ring},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use rustls_pki_types::{
+    CertificateDer, PrivateKeyDer, ServerName,
+    pem::{self, PemObject},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    time::sleep,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector, client::TlsStream};
+
+use crate::config::{
+    ListenerCertificateConfiguration, ListenerTlsConfiguration, TargetGroupTlsConfiguration,
+    UpstreamProtocol,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("Failed to read certificates from: {0}, error: {1}")]
+    ReadCertificates(String, pem::Error),
+    #[error("Failed to read private key from: {0}, error: {1}")]
+    ReadPrivateKey(String, pem::Error),
+    #[error("Invalid certificate or key in: {0}, error: {1}")]
+    InvalidCertificate(String, rustls::Error),
+    #[error("Certificates: {0} and {1} both have no server names")]
+    MultipleDefaultCertificates(String, String),
+    #[error("Invalid server name: {0}")]
+    InvalidServerName(String),
+    #[error("Client certificate and key have to be configured together")]
+    IncompleteClientCertificate,
+    #[error(transparent)]
+    Rustls(rustls::Error),
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn load_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::ReadCertificates(path.to_owned(), e))?;
+
+    if certificates.is_empty() {
+        return Err(TlsError::ReadCertificates(
+            path.to_owned(),
+            pem::Error::NoItemsFound,
+        ));
+    }
+
+    Ok(certificates)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| TlsError::ReadPrivateKey(path.to_owned(), e))
+}
+
+fn parse_server_name(name: &str) -> Result<ServerName<'static>, TlsError> {
+    ServerName::try_from(name.to_owned()).map_err(|_| TlsError::InvalidServerName(name.to_owned()))
+}
+
+/// Listener certificates by server name, replaced as a whole on reload.
+#[derive(Debug, Default)]
+struct CertificateStore {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl CertificateStore {
+    fn load(
+        certificates: &HashMap<String, ListenerCertificateConfiguration>,
+        provider: &CryptoProvider,
+    ) -> Result<Self, TlsError> {
+        let mut store = Self::default();
+        let mut default_name: Option<&str> = None;
+
+        for (name, certificate) in certificates.iter() {
+            let key = CertifiedKey::from_der(
+                load_certificates(&certificate.cert_path)?,
+                load_private_key(&certificate.key_path)?,
+                provider,
+            )
+            .map_err(|e| TlsError::InvalidCertificate(certificate.cert_path.clone(), e))?;
+            let key = Arc::new(key);
+
+            match &certificate.server_names {
+                Some(server_names) => server_names
+                    .split(",")
+                    .map(str::trim)
+                    .filter(|n| !n.is_empty())
+                    .for_each(|n| {
+                        store.by_name.insert(n.to_ascii_lowercase(), key.clone());
+                    }),
+                None => {
+                    if let Some(default_name) = default_name {
+                        return Err(TlsError::MultipleDefaultCertificates(
+                            default_name.to_owned(),
+                            name.clone(),
+                        ));
+                    }
+
+                    default_name = Some(name);
+                    store.default = Some(key);
+                }
+            }
+        }
+
+        Ok(store)
+    }
+
+    fn resolve(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        server_name
+            .map(str::to_ascii_lowercase)
+            .and_then(|name| {
+                self.by_name.get(&name).or_else(|| {
+                    name.split_once(".")
+                        .and_then(|(_, parent)| self.by_name.get(&format!("*.{}", parent)))
+                })
+            })
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+/// Selects the listener certificate by the SNI name of the client and reloads the certificates
+/// once one of their files changed.
+#[derive(Debug)]
+pub struct CertificateResolver {
+    certificates: HashMap<String, ListenerCertificateConfiguration>,
+    provider: Arc<CryptoProvider>,
+    store: RwLock<Arc<CertificateStore>>,
+}
+
+impl CertificateResolver {
+    pub fn new(config: &ListenerTlsConfiguration) -> Result<Arc<Self>, TlsError> {
+        let provider = provider();
+        let store = CertificateStore::load(&config.certificates, &provider)?;
+
+        Ok(Arc::new(Self {
+            certificates: config.certificates.clone(),
+            provider,
+            store: RwLock::new(Arc::new(store)),
+        }))
+    }
+
+    /// Acceptor for client connections, offering HTTP/2 and HTTP/1.1 through ALPN.
+    pub fn acceptor(self: &Arc<Self>) -> Result<TlsAcceptor, TlsError> {
+        let mut config = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(TlsError::Rustls)?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+
+    fn modified_times(&self) -> Vec<Option<SystemTime>> {
+        self.certificates
+            .values()
+            .flat_map(|c| [&c.cert_path, &c.key_path])
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    pub async fn reload_thread(self: Arc<Self>, interval: Duration) {
+        let mut modified = self.modified_times();
+
+        loop {
+            sleep(interval).await;
+
+            let current = self.modified_times();
+
+            if current == modified {
+                continue;
+            }
+
+            modified = current;
+
+            // A failed reload, like of a half written file, keeps the previous certificates.
+            match CertificateStore::load(&self.certificates, &self.provider) {
+                Ok(store) => {
+                    *self
+                        .store
+                        .write()
+                        .expect("Failed to aquire certificate store lock") = Arc::new(store);
+
+                    log::info!("Reloaded listener certificates");
+                }
+                Err(e) => log::error!("Failed to reload listener certificates, error: {}", e),
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.store
+            .read()
+            .expect("Failed to aquire certificate store lock")
+            .resolve(client_hello.server_name())
+    }
+}
+
+/// Client TLS configuration shared by the targets of a group.
+#[derive(Clone)]
+pub struct TargetGroupTls {
+    connector: TlsConnector,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl TargetGroupTls {
+    pub fn new(
+        config: &TargetGroupTlsConfiguration,
+        protocol: UpstreamProtocol,
+    ) -> Result<Self, TlsError> {
+        let mut roots = RootCertStore::empty();
+
+        match &config.ca_path {
+            Some(ca_path) => {
+                for certificate in load_certificates(ca_path)? {
+                    roots
+                        .add(certificate)
+                        .map_err(|e| TlsError::InvalidCertificate(ca_path.clone(), e))?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(TlsError::Rustls)?
+            .with_root_certificates(roots);
+
+        let mut client_config = match (&config.client_cert_path, &config.client_key_path) {
+            (Some(cert_path), Some(key_path)) => builder
+                .with_client_auth_cert(load_certificates(cert_path)?, load_private_key(key_path)?)
+                .map_err(|e| TlsError::InvalidCertificate(cert_path.clone(), e))?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err(TlsError::IncompleteClientCertificate),
+        };
+        client_config.alpn_protocols = match protocol {
+            UpstreamProtocol::Http1 => vec![b"http/1.1".to_vec()],
+            UpstreamProtocol::Http2 => vec![b"h2".to_vec()],
+        };
+
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(client_config)),
+            server_name: config
+                .server_name
+                .as_deref()
+                .map(parse_server_name)
+                .transpose()?,
+        })
+    }
+
+    /// TLS for a single target, verified against its hostname unless the group overrides it.
+    pub fn for_target(&self, hostname: &str) -> Result<TargetTls, TlsError> {
+        let server_name = match &self.server_name {
+            Some(server_name) => server_name.clone(),
+            None => parse_server_name(hostname)?,
+        };
+
+        Ok(TargetTls {
+            connector: self.connector.clone(),
+            server_name,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct TargetTls {
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl TargetTls {
+    pub async fn connect(&self, stream: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+        self.connector
+            .connect(self.server_name.clone(), stream)
+            .await
+    }
+}
+
+impl Debug for TargetTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(format!("TargetTls{{server_name={:?}}}", self.server_name).as_ref())
+    }
+}
+
+/// Connection to a target, with or without TLS.
+pub enum UpstreamStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            Self::Tls(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Self::Plain(stream) => stream.is_write_vectored(),
+            Self::Tls(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair,
+        generate_simple_self_signed,
+    };
+    use rustls::server::WebPkiClientVerifier;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        spawn,
+    };
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rs-lb-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn write(dir: &Path, file: &str, contents: &str) -> String {
+        let path = dir.join(file);
+        fs::write(&path, contents).unwrap();
+
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Writes a self signed certificate for `server_names` and returns its DER encoding.
+    fn write_certificate(
+        dir: &Path,
+        name: &str,
+        server_names: &[&str],
+    ) -> (ListenerCertificateConfiguration, CertificateDer<'static>) {
+        let names = match server_names {
+            [] => vec!["default.test".to_owned()],
+            names => names.iter().map(|n| n.to_string()).collect(),
+        };
+        let certified = generate_simple_self_signed(names).unwrap();
+
+        let config = ListenerCertificateConfiguration {
+            cert_path: write(dir, &format!("{}.crt", name), &certified.cert.pem()),
+            key_path: write(
+                dir,
+                &format!("{}.key", name),
+                &certified.signing_key.serialize_pem(),
+            ),
+            server_names: (!server_names.is_empty()).then(|| server_names.join(", ")),
+        };
+
+        (config, certified.cert.der().clone())
+    }
+
+    fn resolved(store: &CertificateStore, server_name: Option<&str>) -> CertificateDer<'static> {
+        store.resolve(server_name).unwrap().cert[0].clone()
+    }
+
+    #[test]
+    fn resolves_exact_wildcard_and_default_certificates() {
+        let dir = temp_dir();
+        let (exact_config, exact) = write_certificate(&dir, "exact", &["api.example.com"]);
+        let (wildcard_config, wildcard) = write_certificate(&dir, "wildcard", &["*.example.com"]);
+        let (default_config, default) = write_certificate(&dir, "default", &[]);
+
+        let store = CertificateStore::load(
+            &HashMap::from([
+                ("exact".to_owned(), exact_config),
+                ("wildcard".to_owned(), wildcard_config),
+                ("default".to_owned(), default_config),
+            ]),
+            &provider(),
+        )
+        .unwrap();
+
+        assert_eq!(resolved(&store, Some("api.example.com")), exact);
+        assert_eq!(resolved(&store, Some("API.Example.com")), exact);
+        assert_eq!(resolved(&store, Some("web.example.com")), wildcard);
+        // Wildcards only cover a single label.
+        assert_eq!(resolved(&store, Some("a.web.example.com")), default);
+        assert_eq!(resolved(&store, Some("example.org")), default);
+        assert_eq!(resolved(&store, None), default);
+    }
+
+    #[test]
+    fn rejects_multiple_default_certificates() {
+        let dir = temp_dir();
+        let (first, _) = write_certificate(&dir, "first", &[]);
+        let (second, _) = write_certificate(&dir, "second", &[]);
+
+        let loaded = CertificateStore::load(
+            &HashMap::from([("first".to_owned(), first), ("second".to_owned(), second)]),
+            &provider(),
+        );
+
+        assert!(matches!(
+            loaded,
+            Err(TlsError::MultipleDefaultCertificates(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reload_swaps_certificate_and_keeps_it_on_half_written_file() {
+        let dir = temp_dir();
+        let (config, original) = write_certificate(&dir, "listener", &[]);
+        let cert_path = config.cert_path.clone();
+
+        let resolver = CertificateResolver::new(&ListenerTlsConfiguration {
+            certificates: HashMap::from([("listener".to_owned(), config)]),
+            ..Default::default()
+        })
+        .unwrap();
+        let current = || resolved(&resolver.store.read().unwrap(), None);
+
+        spawn(resolver.clone().reload_thread(Duration::from_millis(10)));
+        assert_eq!(current(), original);
+
+        // Lets the reload thread note the original modification times.
+        sleep(Duration::from_millis(20)).await;
+
+        let (_, rewritten) = write_certificate(&dir, "listener", &[]);
+
+        for _ in 0..100 {
+            if current() == rewritten {
+                break;
+            }
+
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(current(), rewritten);
+
+        let pem = fs::read_to_string(&cert_path).unwrap();
+        fs::write(&cert_path, &pem[..pem.len() / 2]).unwrap();
+
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(current(), rewritten);
+    }
+
+    #[tokio::test]
+    async fn upstream_handshake_presents_client_certificate() {
+        let dir = temp_dir();
+
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+
+        let issue = |name: &str| {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec![name.to_owned()])
+                .unwrap()
+                .signed_by(&key, &ca)
+                .unwrap();
+
+            (cert, key)
+        };
+        let (server_cert, server_key) = issue("localhost");
+        let (client_cert, client_key) = issue("client");
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider())
+            .build()
+            .unwrap();
+        let server_config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![server_cert.der().clone()],
+                PrivateKeyDer::try_from(server_key.serialize_der()).unwrap(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if let Ok(mut stream) = acceptor.accept(stream).await {
+                    let _ = stream.write_all(b"ok").await;
+                    let _ = stream.shutdown().await;
+                }
+            }
+        });
+
+        let ca_path = write(&dir, "ca.crt", &ca.pem());
+        let connect = |config: TargetGroupTlsConfiguration| async move {
+            let tls = TargetGroupTls::new(&config, UpstreamProtocol::Http1)
+                .unwrap()
+                .for_target("localhost")
+                .unwrap();
+
+            // With TLS 1.3 a rejected client certificate only fails the first read.
+            let mut stream = tls.connect(TcpStream::connect(addr).await?).await?;
+            let mut read = Vec::new();
+            stream.read_to_end(&mut read).await?;
+
+            Ok::<_, io::Error>(read)
+        };
+
+        let with_client_certificate = connect(TargetGroupTlsConfiguration {
+            ca_path: Some(ca_path.clone()),
+            client_cert_path: Some(write(&dir, "client.crt", &client_cert.pem())),
+            client_key_path: Some(write(&dir, "client.key", &client_key.serialize_pem())),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(with_client_certificate.unwrap(), b"ok");
+
+        let without_client_certificate = connect(TargetGroupTlsConfiguration {
+            ca_path: Some(ca_path),
+            ..Default::default()
+        })
+        .await;
+        assert!(without_client_certificate.is_err());
+    }
+}